@@ -9,8 +9,8 @@ interface FingerprintWasm {
   hash_sha256(data: Uint8Array): Uint8Array;
   hash_combined(sources: any[]): Uint8Array;
   bytes_to_hex(bytes: Uint8Array): string;
-  hash_canvas_data(pixels: Uint8Array, width: number, height: number): string;
-  hash_audio_data(samples: Float32Array): string;
+  hash_canvas_data(pixels: Uint8Array, width: number, height: number, sampleRate: number): string;
+  hash_audio_data(samples: Float32Array, step: number): string;
   generate_fingerprint_id(components: string[]): string;
 }
 
@@ -120,7 +120,8 @@ export function bytesToHex(bytes: Uint8Array): string {
 export async function hashCanvasData(
   pixels: Uint8Array,
   width: number,
-  height: number
+  height: number,
+  sampleRate = 16
 ): Promise<string> {
   if (wasmModule === null) {
     await initFingerprint();
@@ -128,19 +129,20 @@ export async function hashCanvasData(
 
   if (useWasm && wasmModule) {
     try {
-      return wasmModule.hash_canvas_data(pixels, width, height);
+      return wasmModule.hash_canvas_data(pixels, width, height, sampleRate);
     } catch (error) {
       console.warn('WASM canvas hash failed, using fallback:', error);
       useWasm = false;
     }
   }
 
-  // Fallback: sample and hash
-  const samples = new Uint8Array(1024);
-  const sampleRate = Math.floor(pixels.length / 1024);
-  
-  for (let i = 0; i < 1024; i++) {
-    samples[i] = pixels[i * sampleRate] || 0;
+  // Fallback: hash every sampleRate-th RGBA pixel, as the WASM version does
+  const step = Math.max(1, Math.floor(sampleRate));
+  const pixelCount = Math.floor(pixels.length / 4);
+  const samples = new Uint8Array(Math.ceil(pixelCount / step) * 4);
+
+  for (let p = 0, offset = 0; p < pixelCount; p += step, offset += 4) {
+    samples.set(pixels.subarray(p * 4, p * 4 + 4), offset);
   }
   
   const hash = await hashSHA256(samples);
@@ -150,14 +152,14 @@ export async function hashCanvasData(
 /**
  * Hash audio data for fingerprinting
  */
-export async function hashAudioData(samples: Float32Array): Promise<string> {
+export async function hashAudioData(samples: Float32Array, step = 100): Promise<string> {
   if (wasmModule === null) {
     await initFingerprint();
   }
 
   if (useWasm && wasmModule) {
     try {
-      return wasmModule.hash_audio_data(samples);
+      return wasmModule.hash_audio_data(samples, step);
     } catch (error) {
       console.warn('WASM audio hash failed, using fallback:', error);
       useWasm = false;
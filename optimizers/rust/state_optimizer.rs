@@ -1,31 +1,107 @@
 // State Optimizer - Rust implementation for fast VM state compression
 // Compiles to WebAssembly for browser execution
 
-#![no_main]
 use wasm_bindgen::prelude::*;
 
+// Output header: STATE_MAGIC, STATE_VERSION, then an algorithm tag for the body
+const STATE_MAGIC: [u8; 2] = *b"BS";
+const STATE_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4;
+
+// Algorithm tags
+const ALGO_STORED: u8 = 0x00;
+const ALGO_RLE: u8 = 0x01;
+
+// RLE framing: every byte except 0x00 stands for itself. 0x00 is an escape:
+//   00 00        a single literal 0x00
+//   00 n  b      n copies of byte b, n in LEB128
+// The count comes before the byte so that 00 00 can't also start a run of
+// zeros. The original format wrote 00 b n and has to be decoded separately
+// Blobs from before the header existed go through decompress_legacy_state:
+// either a leading RLE_VERSION byte and the same framing, or the original
+// format (see decode_original_runs)
+const RLE_VERSION: u8 = 0x02;
+
+// Largest state a run may decode to, so a corrupt run length errors instead
+// of trying to allocate it
+const MAX_STATE_LEN: usize = 1 << 30;
+
 #[wasm_bindgen]
 pub fn optimize_state(input: &[u8]) -> Vec<u8> {
-    // Fast RLE (Run-Length Encoding) compression optimized for VM state
-    let mut output = Vec::with_capacity(input.len() / 2);
+    let mut output = Vec::with_capacity(input.len() / 2 + HEADER_LEN);
+    output.extend_from_slice(&STATE_MAGIC);
+    output.push(STATE_VERSION);
+    output.push(ALGO_RLE);
+    encode_runs(input, &mut output);
+    
+    // High-entropy input can grow under RLE; store it raw instead
+    if output.len() - HEADER_LEN > input.len() {
+        output.truncate(HEADER_LEN);
+        output[HEADER_LEN - 1] = ALGO_STORED;
+        output.extend_from_slice(input);
+    }
+    output
+}
+
+#[wasm_bindgen]
+pub fn decompress_state(compressed: &[u8]) -> Result<Vec<u8>, JsValue> {
+    decode_state(compressed).map_err(|e| JsValue::from_str(&e))
+}
+
+fn decode_state(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    if !compressed.starts_with(&STATE_MAGIC) {
+        return Err("Missing state header".to_string());
+    }
+    
+    match compressed.get(2..HEADER_LEN) {
+        Some(&[STATE_VERSION, ALGO_STORED]) => Ok(compressed[HEADER_LEN..].to_vec()),
+        Some(&[STATE_VERSION, ALGO_RLE]) => decode_runs(&compressed[HEADER_LEN..]),
+        Some(&[STATE_VERSION, algorithm]) => Err(format!("Unknown state algorithm tag {}", algorithm)),
+        Some(&[version, _]) => Err(format!("Unsupported state format version {}", version)),
+        _ => Err("State header is truncated".to_string()),
+    }
+}
+
+// Decode a blob written before the header existed. Neither format can be told
+// apart from the other by its bytes, so the caller says which one it holds:
+// `varint_counts` for the RLE_VERSION-prefixed stream, otherwise the original
+// unescaped format
+#[wasm_bindgen]
+pub fn decompress_legacy_state(compressed: &[u8], varint_counts: bool) -> Result<Vec<u8>, JsValue> {
+    decode_legacy_state(compressed, varint_counts).map_err(|e| JsValue::from_str(&e))
+}
+
+fn decode_legacy_state(compressed: &[u8], varint_counts: bool) -> Result<Vec<u8>, String> {
+    if !varint_counts {
+        return Ok(decode_original_runs(compressed));
+    }
+    match compressed.split_first() {
+        Some((&RLE_VERSION, body)) => decode_runs(body),
+        _ => Err(format!("Legacy state does not start with version byte {}", RLE_VERSION)),
+    }
+}
+
+// Fast RLE (Run-Length Encoding) compression optimized for VM state
+fn encode_runs(input: &[u8], output: &mut Vec<u8>) {
     let mut i = 0;
     
     while i < input.len() {
         let byte = input[i];
         let mut count = 1;
         
-        // Count consecutive identical bytes (max 255 for single byte encoding)
-        while i + count < input.len() 
-            && input[i + count] == byte 
-            && count < 255 {
+        // Count consecutive identical bytes
+        while i + count < input.len() && input[i + count] == byte {
             count += 1;
         }
         
-        // Encode: if 4+ consecutive bytes or zero byte, use RLE
-        if count > 3 || byte == 0 {
+        if byte == 0 && count == 1 {
+            // Escaped literal zero
+            output.push(0);
+            output.push(0);
+        } else if count > 3 || byte == 0 {
             output.push(0); // RLE marker
+            write_varint(output, count as u64);
             output.push(byte);
-            output.push(count as u8);
         } else {
             // Store raw bytes for small runs
             for _ in 0..count {
@@ -35,28 +111,62 @@ pub fn optimize_state(input: &[u8]) -> Vec<u8> {
         
         i += count;
     }
+}
+
+// Undo the RLE framing. Errors if a run would decode past MAX_STATE_LEN
+fn decode_runs(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut i = 0;
     
-    output
+    while i < compressed.len() {
+        if compressed[i] != 0 {
+            // Raw byte
+            output.push(compressed[i]);
+            i += 1;
+            continue;
+        }
+        if compressed.get(i + 1) == Some(&0) {
+            // Escaped literal zero
+            output.push(0);
+            i += 2;
+            continue;
+        }
+        
+        // RLE marker found; the run may end exactly at the buffer end
+        let mut pos = i + 1;
+        match (read_varint(compressed, &mut pos), compressed.get(pos)) {
+            (Some(count), Some(&byte)) => {
+                let new_len = usize::try_from(count)
+                    .ok()
+                    .and_then(|count| output.len().checked_add(count))
+                    .filter(|&len| len <= MAX_STATE_LEN)
+                    .ok_or_else(|| format!("Run of {} bytes exceeds the {} byte state limit", count, MAX_STATE_LEN))?;
+                output.resize(new_len, byte);
+                i = pos + 1;
+            }
+            // Truncated escape sequence
+            _ => break,
+        }
+    }
+    
+    Ok(output)
 }
 
-#[wasm_bindgen]
-pub fn decompress_state(compressed: &[u8]) -> Vec<u8> {
+// The original framing, with no escape for literal zeros: 00 b n is n copies
+// of byte b (n a single byte, zeros always run-encoded) and every other byte
+// stands for itself. As the original decoder did, a 00 without two bytes
+// after it is taken as a raw zero
+fn decode_original_runs(compressed: &[u8]) -> Vec<u8> {
     let mut output = Vec::new();
     let mut i = 0;
     
     while i < compressed.len() {
         if compressed[i] == 0 && i + 2 < compressed.len() {
-            // RLE marker found
             let byte = compressed[i + 1];
             let count = compressed[i + 2] as usize;
-            
-            for _ in 0..count {
-                output.push(byte);
-            }
-            
+            output.resize(output.len() + count, byte);
             i += 3;
         } else {
-            // Raw byte
             output.push(compressed[i]);
             i += 1;
         }
@@ -65,20 +175,446 @@ pub fn decompress_state(compressed: &[u8]) -> Vec<u8> {
     output
 }
 
+// Per-frame snapshot path: delta against the previous state, RLE-compressed,
+// in a single call across the wasm boundary
+#[wasm_bindgen]
+pub fn delta_and_compress(prev: &[u8], cur: &[u8]) -> Vec<u8> {
+    optimize_state(&deduplicate_state(prev, cur))
+}
+
+// Inverse of delta_and_compress. A corrupt blob is an error whether the
+// damage is in the RLE framing or in the delta it holds
+#[wasm_bindgen]
+pub fn decompress_and_apply(prev: &[u8], blob: &[u8]) -> Result<Vec<u8>, JsValue> {
+    decode_and_apply(prev, blob).map_err(|e| JsValue::from_str(&e))
+}
+
+fn decode_and_apply(prev: &[u8], blob: &[u8]) -> Result<Vec<u8>, String> {
+    apply_delta(prev, &decode_state(blob)?)
+}
+
+// Unsigned LEB128
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Read an unsigned LEB128 at `*pos`, advancing past it. None if it runs off
+// the end or overflows u64
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if shift >= 64 || (shift == 63 && byte > 1) {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+// Delta layout, all numbers LEB128: the length of state2, then runs of
+//   gap, run length, bytes
+// where gap counts the unchanged bytes since the previous run. Bytes of state2
+// past the end of state1 always count as changed
 #[wasm_bindgen]
 pub fn deduplicate_state(state1: &[u8], state2: &[u8]) -> Vec<u8> {
     // Create delta between two states
-    let min_len = state1.len().min(state2.len());
     let mut delta = Vec::new();
+    write_varint(&mut delta, state2.len() as u64);
     
-    for i in 0..min_len {
-        if state1[i] != state2[i] {
-            delta.push(i as u32);
-            delta.push(state2[i] as u32);
+    let changed = |i: usize| state1.get(i) != Some(&state2[i]);
+    let mut prev_end = 0;
+    let mut i = 0;
+    while i < state2.len() {
+        if !changed(i) {
+            i += 1;
+            continue;
+        }
+        
+        // Extend the run, absorbing gaps of up to two unchanged bytes since
+        // a fresh run header would cost at least as much
+        let start = i;
+        let mut end = i + 1;
+        while end < state2.len()
+            && (changed(end) || (end + 1..(end + 3).min(state2.len())).any(changed)) {
+            end += 1;
         }
+        
+        write_varint(&mut delta, (start - prev_end) as u64);
+        write_varint(&mut delta, (end - start) as u64);
+        delta.extend_from_slice(&state2[start..end]);
+        prev_end = end;
+        i = end;
     }
     
-    // Convert to bytes
-    delta.iter().flat_map(|&v| v.to_le_bytes().to_vec()).collect()
+    delta
+}
+
+// Rebuild state2 from state1 and a deduplicate_state delta. state1 is cut or
+// zero-extended to the recorded length before the runs are applied. A delta
+// that can't have come from deduplicate_state is an error: an unreadable
+// length or one longer than state1 plus every byte the delta carries, and a
+// run that is truncated or reaches past the recorded length
+#[wasm_bindgen]
+pub fn apply_state_delta(state1: &[u8], delta: &[u8]) -> Result<Vec<u8>, JsValue> {
+    apply_delta(state1, delta).map_err(|e| JsValue::from_str(&e))
 }
 
+fn apply_delta(state1: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos).ok_or("Delta length is truncated")?;
+    let available = state1.len() + (delta.len() - pos);
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= available)
+        .ok_or_else(|| format!("Delta length {} is more than the {} bytes state1 and the delta supply", len, available))?;
+    
+    let mut state = state1.to_vec();
+    state.resize(len, 0);
+    let mut offset = 0usize;
+    while pos < delta.len() {
+        let (Some(gap), Some(run)) = (read_varint(delta, &mut pos), read_varint(delta, &mut pos)) else {
+            return Err("Delta run header is truncated".to_string());
+        };
+        let start = usize::try_from(gap).ok().and_then(|gap| offset.checked_add(gap));
+        let end = start.zip(usize::try_from(run).ok()).and_then(|(start, run)| start.checked_add(run));
+        let (Some(start), Some(end)) = (start, end) else {
+            return Err("Delta run is outside the state".to_string());
+        };
+        let target = state
+            .get_mut(start..end)
+            .ok_or_else(|| format!("Delta run {}..{} is outside the {} byte state", start, end, len))?;
+        let bytes = pos
+            .checked_add(end - start)
+            .and_then(|run_end| delta.get(pos..run_end))
+            .ok_or_else(|| format!("Delta run of {} bytes is truncated", end - start))?;
+        target.copy_from_slice(bytes);
+        pos += bytes.len();
+        offset = end;
+    }
+    
+    Ok(state)
+}
+
+// Bit-packed flags: every nonzero byte is a set flag. Flag i is bit i % 8
+// (least significant first) of byte i / 8, and unused bits of the last byte
+// are zero. Packing first cuts a flag region 8x before optimize_state's RLE
+#[wasm_bindgen]
+pub fn pack_flags(flags: &[u8]) -> Vec<u8> {
+    flags
+        .chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (bit, &flag)| byte | (((flag != 0) as u8) << bit))
+        })
+        .collect()
+}
+
+// Restore `count` flags as 0/1 bytes from pack_flags output; flags past the
+// end of `packed` read as 0
+#[wasm_bindgen]
+pub fn unpack_flags(packed: &[u8], count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|i| packed.get(i / 8).map_or(0, |byte| (byte >> (i % 8)) & 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed-seed xorshift bytes; `zero_bias` of 0..=255 skews output toward zeros
+    fn random_bytes(seed: u64, len: usize, zero_bias: u8) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let byte = state as u8;
+            if (state >> 8) as u8 <= zero_bias { 0 } else { byte }
+        }).collect()
+    }
+
+    #[test]
+    fn random_buffers_round_trip() {
+        for seed in 0..200u64 {
+            let len = (seed as usize * 37) % 2000;
+            for zero_bias in [0, 64, 200, 255] {
+                let data = random_bytes(seed, len, zero_bias);
+                assert_eq!(decompress_state(&optimize_state(&data)).unwrap(), data, "seed {} bias {}", seed, zero_bias);
+            }
+        }
+    }
+
+    #[test]
+    fn literal_zeros_and_runs_are_distinguished() {
+        let data = [0, 1, 0, 0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 0, 255, 0];
+        let compressed = optimize_state(&data);
+        assert_eq!(compressed[HEADER_LEN..], [0, 0, 1, 0, 2, 0, 0, 10, 2, 0, 0, 255, 0, 0]);
+        assert_eq!(decompress_state(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn trailing_rle_triple_is_restored() {
+        let mut data = vec![1, 2, 3];
+        data.extend([9; 40]);
+        let compressed = optimize_state(&data);
+        assert_eq!(compressed[HEADER_LEN..], [1, 2, 3, 0, 40, 9]);
+        assert_eq!(decompress_state(&compressed).unwrap(), data);
+
+        // A stream that is nothing but one triple
+        assert_eq!(decode_legacy_state(&[RLE_VERSION, 0, 0xff, 0x01, 7], true).unwrap(), vec![7; 255]);
+        // A triple cut short is dropped rather than misread as raw bytes
+        assert_eq!(decode_legacy_state(&[RLE_VERSION, 5, 0, 4], true).unwrap(), vec![5]);
+        assert_eq!(decode_legacy_state(&[RLE_VERSION, 5, 0, 0x84], true).unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn long_runs_collapse_to_one_escape() {
+        let zeros = vec![0u8; 10_000];
+        let compressed = optimize_state(&zeros);
+        assert!(compressed.len() < 10, "{:?}", compressed);
+        assert_eq!(decompress_state(&compressed).unwrap(), zeros);
+
+        let data = random_bytes(7, 300_000, 250);
+        assert_eq!(decompress_state(&optimize_state(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn delta_round_trips_when_lengths_differ() {
+        let state1 = random_bytes(1, 500, 128);
+        let mut state2 = state1.clone();
+        state2[10] ^= 0xff;
+        state2.extend([1, 2, 3, 0, 0, 4]);
+
+        let delta = deduplicate_state(&state1, &state2);
+        assert_eq!(apply_delta(&state1, &delta).unwrap(), state2);
+
+        // Shrinking works the other way round
+        let delta = deduplicate_state(&state2, &state1);
+        assert_eq!(apply_delta(&state2, &delta).unwrap(), state1);
+
+        assert_eq!(apply_delta(&state1, &deduplicate_state(&state1, &[])).unwrap(), Vec::<u8>::new());
+        assert_eq!(apply_delta(&[], &deduplicate_state(&[], &state2)).unwrap(), state2);
+
+    }
+
+    #[test]
+    fn malformed_deltas_are_rejected() {
+        let state1 = random_bytes(1, 500, 128);
+        let mut state2 = state1.clone();
+        state2[10] ^= 0xff;
+        state2[300] ^= 0xff;
+        let delta = deduplicate_state(&state1, &state2);
+
+        assert_eq!(apply_delta(&state1, &[]).unwrap_err(), "Delta length is truncated");
+        // Oversized lengths, past u64 and past what state1 and the delta supply
+        let mut bogus = Vec::new();
+        write_varint(&mut bogus, u64::MAX);
+        assert!(apply_delta(&state1, &bogus).unwrap_err().contains("is more than"));
+        bogus.clear();
+        write_varint(&mut bogus, state1.len() as u64 + 4);
+        bogus.extend([0, 1, 9]);
+        assert!(apply_delta(&state1, &bogus).unwrap_err().contains("is more than"));
+
+        // Cut inside the last run's bytes, and inside its header
+        assert_eq!(apply_delta(&state1, &delta[..delta.len() - 1]).unwrap_err(), "Delta run of 1 bytes is truncated");
+        let mut header_only = delta[..2].to_vec();
+        header_only.push(0x80);
+        assert_eq!(apply_delta(&state1, &header_only).unwrap_err(), "Delta run header is truncated");
+
+        // Runs reaching past the recorded length, or overflowing the offsets
+        bogus.clear();
+        write_varint(&mut bogus, 2);
+        bogus.extend([1, 2, 7, 7]);
+        assert!(apply_delta(&state1, &bogus).unwrap_err().contains("outside"));
+        bogus.clear();
+        write_varint(&mut bogus, 2);
+        bogus.push(0);
+        write_varint(&mut bogus, u64::MAX);
+        assert!(apply_delta(&state1, &bogus).unwrap_err().contains("outside"));
+    }
+
+    #[test]
+    fn contiguous_changes_encode_as_one_run() {
+        let state1 = vec![0u8; 4096];
+        let mut state2 = state1.clone();
+        for byte in &mut state2[1000..1100] {
+            *byte = 0xaa;
+        }
+        // Two isolated changes separated by a short unchanged gap share a run
+        state2[2000] = 1;
+        state2[2002] = 2;
+
+        let delta = deduplicate_state(&state1, &state2);
+        let naive = 8 * 102;
+        assert!(delta.len() < naive, "{} >= {}", delta.len(), naive);
+        assert_eq!(delta.len(), 2 + (2 + 1 + 100) + (2 + 1 + 3));
+        assert_eq!(apply_delta(&state1, &delta).unwrap(), state2);
+    }
+
+    #[test]
+    fn random_deltas_round_trip() {
+        for seed in 0..100u64 {
+            let state1 = random_bytes(seed, (seed as usize * 53) % 1500, 128);
+            let noise = random_bytes(seed + 1000, (seed as usize * 31) % 1500, 230);
+            // Mostly-equal states: noise bytes of zero keep state1's value
+            let state2: Vec<u8> = noise.iter().enumerate()
+                .map(|(i, &n)| if n == 0 { state1.get(i).copied().unwrap_or(0) } else { n })
+                .collect();
+            let delta = deduplicate_state(&state1, &state2);
+            assert_eq!(apply_delta(&state1, &delta).unwrap(), state2, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn v1_header_is_validated() {
+        let compressed = optimize_state(b"hello\0\0\0\0\0world");
+        assert_eq!(compressed[..HEADER_LEN], [b'B', b'S', 1, ALGO_RLE]);
+        assert_eq!(decompress_state(&compressed).unwrap(), b"hello\0\0\0\0\0world");
+
+        let mut future = compressed.clone();
+        future[2] = 9;
+        assert_eq!(decode_state(&future).unwrap_err(), "Unsupported state format version 9");
+        let mut unknown_algorithm = compressed.clone();
+        unknown_algorithm[3] = 0x7f;
+        assert_eq!(decode_state(&unknown_algorithm).unwrap_err(), "Unknown state algorithm tag 127");
+        assert_eq!(decode_state(b"BS\x01").unwrap_err(), "State header is truncated");
+    }
+
+    #[test]
+    fn pre_header_varint_streams_still_decode() {
+        assert_eq!(decode_legacy_state(&[RLE_VERSION, 1, 0, 0xc8, 0x01, 7], true).unwrap(), [&[1][..], &[7; 200]].concat());
+        assert!(decode_legacy_state(&[1, 0, 0xc8, 0x01, 7], true).is_err());
+    }
+
+    /// The encoder `optimize_state` shipped with before the zero escape
+    fn original_optimize_state(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            let byte = input[i];
+            let mut count = 1;
+            while i + count < input.len() && input[i + count] == byte && count < 255 {
+                count += 1;
+            }
+            if count > 3 || byte == 0 {
+                output.extend([0, byte, count as u8]);
+            } else {
+                output.extend(std::iter::repeat_n(byte, count));
+            }
+            i += count;
+        }
+        output
+    }
+
+    #[test]
+    fn pre_escape_blobs_still_decode() {
+        for seed in 0..100u64 {
+            for zero_bias in [0, 128, 250] {
+                let data = random_bytes(seed, (seed as usize * 41) % 1500, zero_bias);
+                let blob = original_optimize_state(&data);
+                assert_eq!(decode_legacy_state(&blob, false).unwrap(), data, "seed {} bias {}", seed, zero_bias);
+            }
+        }
+    }
+
+    #[test]
+    fn headerless_blobs_are_not_guessed() {
+        // An original-format blob that happens to start with RLE_VERSION
+        let legacy = [RLE_VERSION, 0, 7, 200];
+        assert_eq!(decode_legacy_state(&legacy, false).unwrap(), [&[2][..], &[7; 200]].concat());
+        assert_eq!(decode_state(&legacy).unwrap_err(), "Missing state header");
+        // ...or with the magic
+        assert_eq!(decode_legacy_state(b"BS\x01\x01", false).unwrap(), b"BS\x01\x01");
+    }
+
+    #[test]
+    fn oversized_runs_are_rejected() {
+        // 00 <u64::MAX> 07
+        let mut huge = vec![b'B', b'S', STATE_VERSION, ALGO_RLE, 0];
+        write_varint(&mut huge, u64::MAX);
+        huge.push(7);
+        assert!(decode_state(&huge).unwrap_err().contains("state limit"));
+    }
+
+    #[test]
+    fn delta_pipeline_round_trips() {
+        let prev = random_bytes(3, 64 * 1024, 240);
+        let mut cur = prev.clone();
+        for byte in &mut cur[4096..4200] {
+            *byte = byte.wrapping_add(1);
+        }
+        cur.truncate(60 * 1024);
+
+        let blob = delta_and_compress(&prev, &cur);
+        assert!(blob.len() < 200, "{}", blob.len());
+        assert_eq!(decompress_and_apply(&prev, &blob).unwrap(), cur);
+
+        // A blob whose framing is intact but whose delta lost its last byte
+        let delta = decode_state(&blob).unwrap();
+        let corrupted = optimize_state(&delta[..delta.len() - 1]);
+        assert!(decode_and_apply(&prev, &corrupted).unwrap_err().contains("truncated"));
+        assert!(decode_and_apply(&prev, &blob[..2]).is_err());
+    }
+
+    #[test]
+    fn incompressible_input_is_stored() {
+        for seed in 0..20u64 {
+            // Frequent zeros cost two bytes each under RLE
+            let data = random_bytes(seed, 5000, 40);
+            let compressed = optimize_state(&data);
+            assert!(compressed.len() <= data.len() + HEADER_LEN);
+            assert_eq!(compressed[HEADER_LEN - 1], ALGO_STORED);
+            assert_eq!(decompress_state(&compressed).unwrap(), data);
+        }
+
+        let compressible = vec![0u8; 5000];
+        assert_eq!(optimize_state(&compressible)[HEADER_LEN - 1], ALGO_RLE);
+    }
+
+    #[test]
+    fn headerless_streams_use_single_byte_counts() {
+        // Original format: 00 b n with a one-byte count, every zero run-encoded.
+        // Vectors are what the original optimize_state wrote
+        assert_eq!(decode_legacy_state(&[1, 0, 0, 1, 0, 9, 4], false).unwrap(), [1, 0, 9, 9, 9, 9]);
+        assert_eq!(decode_legacy_state(&[0, 0, 5], false).unwrap(), [0; 5]);
+        assert_eq!(decode_legacy_state(&[0, 255, 7], false).unwrap(), [255; 7]);
+        assert_eq!(
+            decode_legacy_state(&[0, 3, 255, 0, 3, 45, 2, 2, 0, 0, 2], false).unwrap(),
+            [&[3; 300][..], &[2, 2, 0, 0]].concat()
+        );
+    }
+
+    #[test]
+    fn flags_pack_eight_to_a_byte_and_unpack() {
+        let flags = [1, 0, 0, 7, 0, 0, 0, 0xff, 0, 1, 1];
+        let packed = pack_flags(&flags);
+        assert_eq!(packed, [0b1000_1001, 0b0000_0110]);
+
+        let expected: Vec<u8> = flags.iter().map(|&flag| (flag != 0) as u8).collect();
+        assert_eq!(unpack_flags(&packed, flags.len()), expected);
+
+        let flags = random_bytes(3, 1001, 230);
+        let packed = pack_flags(&flags);
+        assert_eq!(packed.len(), 126);
+        let unpacked = unpack_flags(&packed, flags.len());
+        assert!(unpacked.iter().zip(&flags).all(|(&bit, &flag)| bit == (flag != 0) as u8));
+        assert_eq!(unpack_flags(&[], 3), [0, 0, 0]);
+    }
+}
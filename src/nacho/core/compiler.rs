@@ -1,56 +1,904 @@
-use super::lifter::IRAp;
+use super::lifter::{IRAp, IRWidth};
+use super::linker::{Linker, ValType};
+use std::collections::{BTreeMap, HashMap};
+
+// Host function that Syscall(id) lowers to, of type (i32) -> ()
+pub const SYSCALL_IMPORT: &str = "syscall";
+
+// Type index of the () -> () signature every lifted block uses
+const BLOCK_TYPE: u8 = 0;
+
+// Lifted code sees one flat address space: the IR address of a Load/Store is
+// used directly as the byte offset into linear memory 0
+const WASM_PAGE_SIZE: u64 = 65536;
+
+// Most pages a wasm32 memory can declare (4 GiB)
+const WASM32_MAX_PAGES: u32 = 65536;
+
+// Export name of the memory configured by Compiler::with_memory
+pub const MEMORY_EXPORT: &str = "memory";
+
+// Register storage lives in the page after the lifted address space: one
+// 8-byte slot per IR register, zeroed by the generated init function
+const REGISTER_FILE_SIZE: u64 = 256 * 8;
+
+// Generated start function that zeroes the register storage
+pub const INIT_FUNCTION: &str = "__init";
+
+// Maps the machine registers a block touches onto dense WASM local indices,
+// in ascending register order
+pub struct RegisterAllocator {
+    locals: BTreeMap<u8, u32>,
+}
+
+impl RegisterAllocator {
+    pub fn new(block: &[IRAp]) -> Self {
+        let mut locals = BTreeMap::new();
+        for reg in block.iter().flat_map(Compiler::registers) {
+            locals.insert(reg, 0);
+        }
+        for (idx, local) in locals.values_mut().enumerate() {
+            *local = idx as u32;
+        }
+        RegisterAllocator { locals }
+    }
+
+    // Local index for a register; panics if the block never referenced it
+    pub fn local(&self, reg: u8) -> u32 {
+        self.locals[&reg]
+    }
+
+    pub fn local_count(&self) -> u32 {
+        self.locals.len() as u32
+    }
+
+    // Register -> local index
+    pub fn mapping(&self) -> &BTreeMap<u8, u32> {
+        &self.locals
+    }
+}
 
 pub struct Compiler {
     // Configuration for optimization levels, etc.
     pub optimization_level: u8,
+    // Minimum and maximum pages of an exported, growable memory; None emits
+    // an unexported memory sized to the lifted code
+    pub memory_limits: Option<(u32, u32)>,
 }
 
 impl Compiler {
     pub fn new(optimization_level: u8) -> Self {
-        Compiler { optimization_level }
+        Compiler { optimization_level, memory_limits: None }
+    }
+
+    // Emit a memory limited to min_pages..=max_pages and export it as "memory"
+    // so the host and imports (malloc stubs) share the heap with lifted code.
+    // The minimum is raised to what the lifted code and register file need;
+    // compile rejects a maximum above the wasm32 limit of 65536 pages
+    pub fn with_memory(mut self, min_pages: u32, max_pages: u32) -> Self {
+        self.memory_limits = Some((min_pages, max_pages));
+        self
     }
 
-    // Compile IR blocks into WebAssembly bytecode
-    pub fn compile(&self, blocks: &std::collections::HashMap<u64, Vec<IRAp>>) -> Result<Vec<u8>, String> {
+    // Compile IR blocks into WebAssembly bytecode. Host imports the blocks need
+    // are registered with the linker, and lifted functions are numbered after them
+    pub fn compile(&self, blocks: &HashMap<u64, Vec<IRAp>>, linker: &mut Linker) -> Result<Vec<u8>, String> {
         let mut wasm_module = Vec::new();
 
         // WASM Magic Header
         wasm_module.extend_from_slice(&[0x00, 0x61, 0x73, 0x6d]);
         wasm_module.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
 
-        // Type Section
-        // Function Section
-        // Export Section
+        let has_syscalls = blocks.values().flatten().any(|op| matches!(op, IRAp::Syscall(_)));
+        if has_syscalls && linker.import_index(SYSCALL_IMPORT).is_none() {
+            linker.define_import(SYSCALL_IMPORT.to_string(), vec![ValType::I32], Vec::new());
+        }
+        let import_count = linker.imports.len();
+        let relocations = linker.relocate(blocks)?;
+
+        // Emit in address order so identical input always gives identical bytes
+        let mut blocks: Vec<(&u64, &Vec<IRAp>)> = blocks.iter().collect();
+        blocks.sort_unstable_by_key(|(addr, _)| **addr);
+
+        // Type Section: the block type, then the linker's import signatures
+        let import_types = linker.import_types();
+        let mut types = Vec::new();
+        write_uleb128(&mut types, 1 + import_types.len() as u64);
+        types.extend_from_slice(&[0x60, 0x00, 0x00]); // BLOCK_TYPE
+        for import_type in &import_types {
+            import_type.encode(&mut types);
+        }
+        section(&mut wasm_module, 0x01, &types);
+
+        // Import Section
+        wasm_module.extend(linker.generate_import_section());
+
+        // Function Section: every block, then the init function
+        let init_index = import_count + blocks.len();
+        let mut functions = Vec::new();
+        write_uleb128(&mut functions, blocks.len() as u64 + 1);
+        functions.extend(blocks.iter().map(|_| BLOCK_TYPE));
+        functions.push(BLOCK_TYPE);
+        section(&mut wasm_module, 0x03, &functions);
+
+        // Memory Section: one memory large enough for every address accessed,
+        // plus a page for the register file
+        let data_pages = Self::memory_pages(blocks.iter().map(|(_, block)| block.as_slice()));
+        let register_file = match data_pages.checked_mul(WASM_PAGE_SIZE) {
+            Some(start) if start.checked_add(REGISTER_FILE_SIZE).is_some_and(|end| end <= u32::MAX as u64) => start,
+            _ => return Err("no room for the register file in 32-bit linear memory".to_string()),
+        };
+        let mut memory = vec![0x01]; // one memory
+        match self.memory_limits {
+            Some((_, max_pages)) if max_pages > WASM32_MAX_PAGES => {
+                return Err(format!(
+                    "memory maximum of {} pages exceeds the wasm32 limit of {}",
+                    max_pages, WASM32_MAX_PAGES
+                ));
+            }
+            Some((min_pages, max_pages)) => {
+                let min_pages = (min_pages as u64).max(data_pages + 1);
+                if min_pages > max_pages as u64 {
+                    return Err(format!(
+                        "lifted code needs {} memory pages, more than the maximum of {}",
+                        min_pages, max_pages
+                    ));
+                }
+                memory.push(0x01); // min and max
+                write_uleb128(&mut memory, min_pages);
+                write_uleb128(&mut memory, max_pages as u64);
+            }
+            None => {
+                memory.push(0x00); // min only
+                write_uleb128(&mut memory, data_pages + 1);
+            }
+        }
+        section(&mut wasm_module, 0x05, &memory);
+
+        // Export Section: each block under its address, then the memory when
+        // it is shared with the host
+        let mut exports = Vec::new();
+        write_uleb128(&mut exports, blocks.len() as u64 + self.memory_limits.is_some() as u64);
+        for (idx, (addr, _)) in blocks.iter().enumerate() {
+            let name = export_name(**addr);
+            write_uleb128(&mut exports, name.len() as u64);
+            exports.extend_from_slice(name.as_bytes());
+            exports.push(0x00); // func
+            write_uleb128(&mut exports, (import_count + idx) as u64);
+        }
+        if self.memory_limits.is_some() {
+            write_uleb128(&mut exports, MEMORY_EXPORT.len() as u64);
+            exports.extend_from_slice(MEMORY_EXPORT.as_bytes());
+            exports.extend_from_slice(&[0x02, 0x00]); // memory 0
+        }
+        section(&mut wasm_module, 0x07, &exports);
+
+        // Start Section: run the init function on instantiation, before any
+        // exported block can be called
+        let mut start = Vec::new();
+        write_uleb128(&mut start, init_index as u64);
+        section(&mut wasm_module, 0x08, &start);
+
         // Code Section
+        let mut code = Vec::new();
+        write_uleb128(&mut code, blocks.len() as u64 + 1);
+        for (_, block) in &blocks {
+            let mut block = block.to_vec();
+            self.optimize(&mut block);
+            let body = self.compile_block(&block, linker, &relocations)?;
+            write_uleb128(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+        let body = Self::init_body(register_file);
+        write_uleb128(&mut code, body.len() as u64);
+        code.extend(body);
+        section(&mut wasm_module, 0x0a, &code);
+
+        // Name Section, for readable stack traces
+        wasm_module.extend(linker.generate_name_section());
+
+        Ok(wasm_module)
+    }
+
+    // Emit one block as a WASM function body: local declarations, the lowered
+    // instructions and the closing end. Registers live in i64 locals assigned by
+    // RegisterAllocator; 32-bit ops wrap their inputs and zero-extend the result.
+    // `functions` is the linker's relocation map from code address to function
+    // index; a Jmp becomes a call to its target followed by a return
+    pub fn compile_block(&self, block: &[IRAp], linker: &Linker, functions: &HashMap<u64, u32>) -> Result<Vec<u8>, String> {
+        let regs = RegisterAllocator::new(block);
+        let local_count = regs.local_count() as u64;
+
+        let mut body = Vec::new();
+        if local_count == 0 {
+            body.push(0x00); // no local groups
+        } else {
+            body.push(0x01); // one group of i64 locals
+            write_uleb128(&mut body, local_count);
+            body.push(0x7e); // i64
+        }
 
-        // Iterate over blocks and generate WASM opcodes
-        for (addr, block) in blocks {
-            // Emit WASM function for this block
-            for op in block {
-                match op {
-                    IRAp::Add(_, _, _) => {
-                        // Emit i32.add or i64.add
-                        wasm_module.push(0x6a); // i32.add
+        for op in block {
+            match op {
+                IRAp::Mov(dest, src, width) => {
+                    body.push(0x20); // local.get
+                    write_uleb128(&mut body, regs.local(*src) as u64);
+                    if *width == IRWidth::W32 {
+                        body.extend_from_slice(&[0xa7, 0xad]); // i32.wrap_i64; i64.extend_i32_u
                     }
-                    IRAp::Sub(_, _, _) => {
-                        wasm_module.push(0x6b); // i32.sub
+                    body.push(0x21); // local.set
+                    write_uleb128(&mut body, regs.local(*dest) as u64);
+                }
+                IRAp::Add(dest, a, b, width) => Self::emit_binop(&mut body, &regs, *dest, *a, *b, *width, (0x6a, 0x7c)),
+                IRAp::Sub(dest, a, b, width) => Self::emit_binop(&mut body, &regs, *dest, *a, *b, *width, (0x6b, 0x7d)),
+                IRAp::Mul(dest, a, b, width) => Self::emit_binop(&mut body, &regs, *dest, *a, *b, *width, (0x6c, 0x7e)),
+                IRAp::Div(dest, a, b, width) => Self::emit_binop(&mut body, &regs, *dest, *a, *b, *width, (0x6e, 0x80)),
+                IRAp::Load(reg, addr, width) => {
+                    Self::emit_address(&mut body, *addr, *width)?;
+                    body.extend_from_slice(&Self::load_op(*width));
+                    body.push(0x21); // local.set
+                    write_uleb128(&mut body, regs.local(*reg) as u64);
+                }
+                IRAp::Store(addr, reg, width) => {
+                    Self::emit_address(&mut body, *addr, *width)?;
+                    body.push(0x20); // local.get
+                    write_uleb128(&mut body, regs.local(*reg) as u64);
+                    body.extend_from_slice(&Self::store_op(*width));
+                }
+                // The address is the low 32 bits of a register, only known at
+                // run time; an access past the end of memory traps
+                IRAp::LoadIndirect(reg, addr_reg, width) => {
+                    body.push(0x20); // local.get
+                    write_uleb128(&mut body, regs.local(*addr_reg) as u64);
+                    body.push(0xa7); // i32.wrap_i64
+                    body.extend_from_slice(&Self::load_op(*width));
+                    body.push(0x21); // local.set
+                    write_uleb128(&mut body, regs.local(*reg) as u64);
+                }
+                IRAp::StoreIndirect(addr_reg, reg, width) => {
+                    body.push(0x20); // local.get
+                    write_uleb128(&mut body, regs.local(*addr_reg) as u64);
+                    body.push(0xa7); // i32.wrap_i64
+                    body.push(0x20); // local.get
+                    write_uleb128(&mut body, regs.local(*reg) as u64);
+                    body.extend_from_slice(&Self::store_op(*width));
+                }
+                IRAp::Const(dest, value) => {
+                    body.push(0x42); // i64.const
+                    write_sleb128(&mut body, *value as i64);
+                    body.push(0x21); // local.set
+                    write_uleb128(&mut body, regs.local(*dest) as u64);
+                }
+                IRAp::Syscall(id) => {
+                    let import = linker
+                        .import_index(SYSCALL_IMPORT)
+                        .ok_or_else(|| format!("{} import is not registered with the linker", SYSCALL_IMPORT))?;
+                    body.push(0x41); // i32.const
+                    write_sleb128(&mut body, *id as i32 as i64);
+                    body.push(0x10); // call
+                    write_uleb128(&mut body, import as u64);
+                }
+                IRAp::Call(target) | IRAp::Jmp(target) => {
+                    let idx = functions
+                        .get(target)
+                        .ok_or_else(|| format!("no relocation for target {:#x}", target))?;
+                    body.push(0x10); // call
+                    write_uleb128(&mut body, *idx as u64);
+                    if matches!(op, IRAp::Jmp(_)) {
+                        body.push(0x0f); // return
                     }
-                    IRAp::Ret => {
-                        wasm_module.push(0x0b); // end
+                }
+                IRAp::Ret => {
+                    body.push(0x0f); // return
+                }
+                _ => {
+                    // Handle other ops
+                }
+            }
+        }
+
+        body.push(0x0b); // end
+        Ok(body)
+    }
+
+    // Body of the init function: memory.fill the register file with zeros so
+    // lifted code never observes stale register state
+    fn init_body(register_file: u64) -> Vec<u8> {
+        let mut body = vec![0x00]; // no locals
+        body.push(0x41); // i32.const
+        write_sleb128(&mut body, register_file as u32 as i32 as i64);
+        body.extend_from_slice(&[0x41, 0x00]); // i32.const 0
+        body.push(0x41); // i32.const
+        write_sleb128(&mut body, REGISTER_FILE_SIZE as i64);
+        body.extend_from_slice(&[0xfc, 0x0b, 0x00]); // memory.fill
+        body.push(0x0b); // end
+        body
+    }
+
+    // i32.const of a memory address, which must fit the 32-bit linear memory
+    fn emit_address(body: &mut Vec<u8>, addr: u64, width: IRWidth) -> Result<(), String> {
+        if addr.checked_add(Self::access_size(width)).is_none_or(|end| end > u32::MAX as u64) {
+            return Err(format!("address {:#x} is outside 32-bit linear memory", addr));
+        }
+        body.push(0x41); // i32.const
+        write_sleb128(body, addr as u32 as i32 as i64);
+        Ok(())
+    }
+
+    // i64.load32_u / i64.load with natural alignment and no offset
+    fn load_op(width: IRWidth) -> [u8; 3] {
+        match width {
+            IRWidth::W32 => [0x35, 0x02, 0x00],
+            IRWidth::W64 => [0x29, 0x03, 0x00],
+        }
+    }
+
+    // i64.store32 / i64.store with natural alignment and no offset
+    fn store_op(width: IRWidth) -> [u8; 3] {
+        match width {
+            IRWidth::W32 => [0x3e, 0x02, 0x00],
+            IRWidth::W64 => [0x37, 0x03, 0x00],
+        }
+    }
+
+    fn access_size(width: IRWidth) -> u64 {
+        match width {
+            IRWidth::W32 => 4,
+            IRWidth::W64 => 8,
+        }
+    }
+
+    // Pages needed to cover every Load/Store, at least one. Indirect accesses
+    // aren't counted: their addresses are only known at run time
+    fn memory_pages<'a>(blocks: impl Iterator<Item = &'a [IRAp]>) -> u64 {
+        let end = blocks
+            .flatten()
+            .filter_map(|op| match op {
+                IRAp::Load(_, addr, width) | IRAp::Store(addr, _, width) => {
+                    Some(addr.saturating_add(Self::access_size(*width)))
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        end.div_ceil(WASM_PAGE_SIZE).max(1)
+    }
+
+    // Register operands read or written by an op
+    fn registers(op: &IRAp) -> Vec<u8> {
+        match op {
+            IRAp::Mov(d, s, _) | IRAp::LoadIndirect(d, s, _) | IRAp::StoreIndirect(d, s, _) => vec![*d, *s],
+            IRAp::Add(d, a, b, _) | IRAp::Sub(d, a, b, _) | IRAp::Mul(d, a, b, _) | IRAp::Div(d, a, b, _) => {
+                vec![*d, *a, *b]
+            }
+            IRAp::Load(reg, _, _) | IRAp::Store(_, reg, _) | IRAp::Bz(reg, _, _) | IRAp::Const(reg, _) => vec![*reg],
+            _ => Vec::new(),
+        }
+    }
+
+    // local.get a; local.get b; <op>; local.set dest, with the i32 opcode
+    // (plus wrap/extend) for W32 and the i64 opcode for W64
+    fn emit_binop(body: &mut Vec<u8>, regs: &RegisterAllocator, dest: u8, a: u8, b: u8, width: IRWidth, (op32, op64): (u8, u8)) {
+        for src in [a, b] {
+            body.push(0x20); // local.get
+            write_uleb128(body, regs.local(src) as u64);
+            if width == IRWidth::W32 {
+                body.push(0xa7); // i32.wrap_i64
+            }
+        }
+        match width {
+            IRWidth::W32 => body.extend_from_slice(&[op32, 0xad]), // op; i64.extend_i32_u
+            IRWidth::W64 => body.push(op64),
+        }
+        body.push(0x21); // local.set
+        write_uleb128(body, regs.local(dest) as u64);
+    }
+
+    // Peephole passes, gated on optimization_level:
+    // 0 = none, 1 = constant folding, identities and load/store folding,
+    // 2 = + dead-load elimination
+    pub fn optimize(&self, ir: &mut Vec<IRAp>) {
+        if self.optimization_level >= 1 {
+            Self::fold_constants(ir);
+            Self::remove_identities(ir);
+            Self::fold_load_store(ir);
+        }
+        if self.optimization_level >= 2 {
+            Self::remove_dead_loads(ir);
+        }
+    }
+
+    // Forward constant propagation: arithmetic whose sources both hold known
+    // constants becomes a Const of the result. Division by a known zero is left
+    // alone so it still traps at runtime
+    fn fold_constants(ir: &mut [IRAp]) {
+        let mut known: HashMap<u8, u64> = HashMap::new();
+        for op in ir.iter_mut() {
+            let folded = match *op {
+                IRAp::Mov(d, s, w) => known.get(&s).map(|&v| match w {
+                    IRWidth::W32 => IRAp::Const(d, v as u32 as u64),
+                    IRWidth::W64 => IRAp::Const(d, v),
+                }),
+                IRAp::Add(d, a, b, w) | IRAp::Sub(d, a, b, w) | IRAp::Mul(d, a, b, w) | IRAp::Div(d, a, b, w) => {
+                    match (known.get(&a), known.get(&b)) {
+                        (Some(&x), Some(&y)) => Self::eval(op, x, y, w).map(|v| IRAp::Const(d, v)),
+                        _ => None,
                     }
-                    _ => {
-                        // Handle other ops
+                }
+                _ => None,
+            };
+            if let Some(folded) = folded {
+                *op = folded;
+            }
+
+            match *op {
+                IRAp::Const(d, v) => {
+                    known.insert(d, v);
+                }
+                IRAp::Mov(d, ..) | IRAp::Add(d, ..) | IRAp::Sub(d, ..) | IRAp::Mul(d, ..) | IRAp::Div(d, ..)
+                | IRAp::Load(d, ..) | IRAp::LoadIndirect(d, ..) => {
+                    known.remove(&d);
+                }
+                // Calls and syscalls may clobber any register
+                IRAp::Call(_) | IRAp::Syscall(_) => known.clear(),
+                _ => {}
+            }
+        }
+    }
+
+    // Result of an arithmetic op on constant inputs, truncated to its width
+    fn eval(op: &IRAp, x: u64, y: u64, width: IRWidth) -> Option<u64> {
+        let value = match width {
+            IRWidth::W32 => {
+                let (x, y) = (x as u32, y as u32);
+                (match op {
+                    IRAp::Add(..) => x.wrapping_add(y),
+                    IRAp::Sub(..) => x.wrapping_sub(y),
+                    IRAp::Mul(..) => x.wrapping_mul(y),
+                    IRAp::Div(..) => x.checked_div(y)?,
+                    _ => return None,
+                }) as u64
+            }
+            IRWidth::W64 => match op {
+                IRAp::Add(..) => x.wrapping_add(y),
+                IRAp::Sub(..) => x.wrapping_sub(y),
+                IRAp::Mul(..) => x.wrapping_mul(y),
+                IRAp::Div(..) => x.checked_div(y)?,
+                _ => return None,
+            },
+        };
+        Some(value)
+    }
+
+    // Drop 64-bit moves of a register onto itself. A 32-bit self-move still
+    // zero-extends the upper half, so it is kept
+    fn remove_identities(ir: &mut Vec<IRAp>) {
+        ir.retain(|op| !matches!(op, IRAp::Mov(d, s, IRWidth::W64) if d == s));
+    }
+
+    // Adjacent memory ops on the same address and width:
+    //   Store(a, r); Load(r, a)  -> the load is redundant
+    //   Load(r, a); Store(a, r)  -> the store writes back what was read
+    //   Store(a, r); Store(a, s) -> the first store is overwritten
+    fn fold_load_store(ir: &mut Vec<IRAp>) {
+        let mut out: Vec<IRAp> = Vec::with_capacity(ir.len());
+        for op in ir.drain(..) {
+            match (out.last(), &op) {
+                (Some(IRAp::Store(a, r, w)), IRAp::Load(r2, a2, w2))
+                | (Some(IRAp::Load(r, a, w)), IRAp::Store(a2, r2, w2))
+                    if a == a2 && r == r2 && w == w2 => continue,
+                (Some(IRAp::Store(a, _, w)), IRAp::Store(a2, _, w2)) if a == a2 && w == w2 => {
+                    out.pop();
+                }
+                _ => {}
+            }
+            out.push(op);
+        }
+        *ir = out;
+    }
+
+    // Remove Loads (and Consts) whose register is written again before anything
+    // reads it. Control flow and syscalls count as reads of every register
+    fn remove_dead_loads(ir: &mut Vec<IRAp>) {
+        let mut dead = vec![false; ir.len()];
+        for (idx, op) in ir.iter().enumerate() {
+            let (IRAp::Load(reg, _, _) | IRAp::Const(reg, _)) = op else { continue };
+            for later in &ir[idx + 1..] {
+                let (reads, writes) = match later {
+                    IRAp::Mov(d, s, _) | IRAp::LoadIndirect(d, s, _) => (s == reg, d == reg),
+                    IRAp::StoreIndirect(a, r, _) => (a == reg || r == reg, false),
+                    IRAp::Add(d, a, b, _) | IRAp::Sub(d, a, b, _) | IRAp::Mul(d, a, b, _) | IRAp::Div(d, a, b, _) => {
+                        (a == reg || b == reg, d == reg)
                     }
+                    IRAp::Load(d, _, _) | IRAp::Const(d, _) => (false, d == reg),
+                    IRAp::Store(_, r, _) | IRAp::Bz(r, _, _) => (r == reg, false),
+                    IRAp::Jmp(_) | IRAp::Call(_) | IRAp::Ret | IRAp::Syscall(_) => (true, false),
+                };
+                if reads {
+                    break;
+                }
+                if writes {
+                    dead[idx] = true;
+                    break;
                 }
             }
         }
+        let mut flags = dead.into_iter();
+        ir.retain(|_| !flags.next().unwrap());
+    }
+}
 
-        Ok(wasm_module)
+
+// Export name for the function lifted from the block at `addr`
+pub fn export_name(addr: u64) -> String {
+    format!("{:#x}", addr)
+}
+
+// Append a section: id, LEB128 size, contents
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_uleb128(out, contents.len() as u64);
+    out.extend_from_slice(contents);
+}
+
+// Unsigned LEB128, as used for WASM indices and counts
+pub fn write_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
     }
+}
 
-    pub fn optimize(&self, ir: &mut Vec<IRAp>) {
-        // Simple peephole optimization
-        // E.g., remove Add(x, x, 0)
+// Signed LEB128, as used for i64.const immediates
+pub fn write_sleb128(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Wrap a single () -> () function body in a minimal module
+    fn module_with_body(body: &[u8]) -> Vec<u8> {
+        let mut module = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        module.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type: () -> ()
+        module.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]); // func 0 has type 0
+        let mut code = vec![0x01];
+        write_uleb128(&mut code, body.len() as u64);
+        code.extend_from_slice(body);
+        module.push(0x0a);
+        write_uleb128(&mut module, code.len() as u64);
+        module.extend(code);
+        module
+    }
+
+    fn uleb(value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_uleb128(&mut out, value);
+        out
+    }
+
+    fn sleb(value: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_sleb128(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn leb128_known_encodings() {
+        assert_eq!(uleb(0), [0x00]);
+        assert_eq!(uleb(127), [0x7f]);
+        assert_eq!(uleb(128), [0x80, 0x01]);
+        assert_eq!(uleb(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(uleb(u64::MAX), [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+
+        assert_eq!(sleb(0), [0x00]);
+        assert_eq!(sleb(63), [0x3f]);
+        assert_eq!(sleb(64), [0xc0, 0x00]);
+        assert_eq!(sleb(-1), [0x7f]);
+        assert_eq!(sleb(-64), [0x40]);
+        assert_eq!(sleb(-65), [0xbf, 0x7f]);
+        assert_eq!(sleb(-123456), [0xc0, 0xbb, 0x78]);
+        assert_eq!(sleb(i64::MIN), [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]);
+    }
+
+    #[test]
+    fn block_body_validates() {
+        let block = vec![
+            IRAp::Add(0, 1, 2, IRWidth::W64),
+            IRAp::Sub(2, 0, 1, IRWidth::W32),
+            IRAp::Mov(3, 2, IRWidth::W64),
+            IRAp::Mov(4, 0, IRWidth::W32),
+            IRAp::Ret,
+        ];
+        let body = Compiler::new(0).compile_block(&block, &Linker::new(), &HashMap::new()).unwrap();
+        wasmparser::validate(&module_with_body(&body)).unwrap();
+    }
+
+    #[test]
+    fn registers_are_packed_into_dense_locals() {
+        let block = vec![IRAp::Add(7, 0, 3, IRWidth::W64)];
+        let regs = RegisterAllocator::new(&block);
+        assert_eq!(regs.local_count(), 3);
+        assert_eq!(regs.mapping().iter().map(|(r, l)| (*r, *l)).collect::<Vec<_>>(), vec![(0, 0), (3, 1), (7, 2)]);
+
+        let body = Compiler::new(0).compile_block(&block, &Linker::new(), &HashMap::new()).unwrap();
+        assert_eq!(
+            body,
+            vec![
+                0x01, 0x03, 0x7e, // three i64 locals
+                0x20, 0x00, 0x20, 0x01, 0x7c, 0x21, 0x02, // local 0 + local 1 -> local 2
+                0x0b,
+            ]
+        );
+    }
+
+    #[test]
+    fn self_move_is_removed_from_level_1() {
+        // Operand 0 is register 0, so Add(x, x, 0) is a real addition
+        let ir = vec![
+            IRAp::Mov(4, 4, IRWidth::W64),
+            IRAp::Mov(5, 5, IRWidth::W32),
+            IRAp::Add(3, 3, 0, IRWidth::W64),
+            IRAp::Mov(4, 1, IRWidth::W64),
+            IRAp::Ret,
+        ];
+
+        let mut kept = ir.clone();
+        Compiler::new(0).optimize(&mut kept);
+        assert_eq!(kept, ir);
+
+        let mut optimized = ir.clone();
+        Compiler::new(1).optimize(&mut optimized);
+        assert_eq!(optimized, ir[1..].to_vec());
+    }
+
+    #[test]
+    fn adjacent_loads_and_stores_fold() {
+        let mut ir = vec![
+            IRAp::Store(0x10, 1, IRWidth::W64),
+            IRAp::Store(0x10, 2, IRWidth::W64),
+            IRAp::Load(2, 0x10, IRWidth::W64),
+            IRAp::Load(3, 0x20, IRWidth::W32),
+            IRAp::Store(0x20, 3, IRWidth::W32),
+        ];
+        Compiler::new(1).optimize(&mut ir);
+        assert_eq!(ir, vec![IRAp::Store(0x10, 2, IRWidth::W64), IRAp::Load(3, 0x20, IRWidth::W32)]);
+    }
+
+    #[test]
+    fn overwritten_loads_are_dead_at_level_2() {
+        let ir = vec![
+            IRAp::Load(1, 0x10, IRWidth::W64),
+            IRAp::Load(1, 0x18, IRWidth::W64),
+            IRAp::Add(2, 1, 1, IRWidth::W64),
+            IRAp::Ret,
+        ];
+
+        let mut level1 = ir.clone();
+        Compiler::new(1).optimize(&mut level1);
+        assert_eq!(level1, ir);
+
+        let mut level2 = ir.clone();
+        Compiler::new(2).optimize(&mut level2);
+        assert_eq!(level2, ir[1..].to_vec());
+    }
+
+    #[test]
+    fn constant_add_folds_to_const() {
+        let mut ir = vec![
+            IRAp::Const(1, 40),
+            IRAp::Const(2, 2),
+            IRAp::Add(3, 1, 2, IRWidth::W64),
+            IRAp::Sub(4, 1, 2, IRWidth::W32),
+            IRAp::Sub(5, 2, 1, IRWidth::W32),
+        ];
+        Compiler::new(1).optimize(&mut ir);
+        assert_eq!(ir[2], IRAp::Const(3, 42));
+        assert_eq!(ir[3], IRAp::Const(4, 38));
+        assert_eq!(ir[4], IRAp::Const(5, 0xffff_ffda));
+
+        // Both sources overwritten by the folded result leaves one Const at level 2
+        let mut ir = vec![IRAp::Const(1, 40), IRAp::Const(1, 2), IRAp::Add(1, 1, 1, IRWidth::W64), IRAp::Ret];
+        Compiler::new(2).optimize(&mut ir);
+        assert_eq!(ir, vec![IRAp::Const(1, 4), IRAp::Ret]);
+    }
+
+    #[test]
+    fn const_lowers_to_a_valid_body() {
+        let block = vec![IRAp::Const(0, u64::MAX), IRAp::Const(1, 624485), IRAp::Add(0, 0, 1, IRWidth::W64)];
+        let body = Compiler::new(0).compile_block(&block, &Linker::new(), &HashMap::new()).unwrap();
+        wasmparser::validate(&module_with_body(&body)).unwrap();
+    }
+
+    #[test]
+    fn compiled_module_validates_and_exports_blocks() {
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Add(0, 1, 2, IRWidth::W64), IRAp::Ret]);
+        blocks.insert(0x1010, vec![IRAp::Mul(3, 3, 3, IRWidth::W32)]);
+        let module = Compiler::new(0).compile(&blocks, &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+
+        let mut names = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(&module) {
+            if let wasmparser::Payload::ExportSection(reader) = payload.unwrap() {
+                for export in reader {
+                    names.push(export.unwrap().name.to_string());
+                }
+            }
+        }
+        assert_eq!(names, vec!["0x1000", "0x1010"]);
+    }
+
+    #[test]
+    fn syscall_calls_the_linked_import() {
+        let mut linker = Linker::new();
+        linker.resolve_imports(vec!["puts".to_string()]);
+
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Syscall(4)]);
+        let module = Compiler::new(0).compile(&blocks, &mut linker).unwrap();
+        wasmparser::validate(&module).unwrap();
+        assert_eq!(linker.import_index(SYSCALL_IMPORT), Some(1));
+
+        let body = Compiler::new(0).compile_block(&blocks[&0x1000], &linker, &HashMap::new()).unwrap();
+        assert_eq!(body, vec![0x00, 0x41, 0x04, 0x10, 0x01, 0x0b]);
+
+        assert!(Compiler::new(0).compile_block(&blocks[&0x1000], &Linker::new(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn store_then_load_round_trips_through_memory() {
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![
+            IRAp::Const(1, 7),
+            IRAp::Store(0x2_0000, 1, IRWidth::W64),
+            IRAp::Load(2, 0x2_0000, IRWidth::W64),
+            IRAp::Load(3, 0x2_0000, IRWidth::W32),
+            IRAp::Ret,
+        ]);
+        let module = Compiler::new(0).compile(&blocks, &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+
+        let body = Compiler::new(0).compile_block(&blocks[&0x1000], &Linker::new(), &HashMap::new()).unwrap();
+        // i32.const 0x20000; local.get 0; i64.store
+        assert!(body.windows(9).any(|w| w == [0x41, 0x80, 0x80, 0x08, 0x20, 0x00, 0x37, 0x03, 0x00]));
+
+        // 0x20008 bytes need three pages
+        assert_eq!(Compiler::memory_pages([blocks[&0x1000].as_slice()].into_iter()), 3);
+
+        let out_of_range = vec![IRAp::Load(0, 0xffff_fffe, IRWidth::W32)];
+        assert!(Compiler::new(0).compile_block(&out_of_range, &Linker::new(), &HashMap::new()).is_err());
+        let wrapping = vec![IRAp::Load(0, u64::MAX - 3, IRWidth::W64)];
+        assert!(Compiler::new(0).compile_block(&wrapping, &Linker::new(), &HashMap::new()).is_err());
+
+        // Register addresses are wrapped to i32 at run time
+        let indirect = vec![IRAp::StoreIndirect(4, 1, IRWidth::W64), IRAp::LoadIndirect(2, 4, IRWidth::W32)];
+        let module = Compiler::new(0).compile(&HashMap::from([(0x1000, indirect.clone())]), &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+        let body = Compiler::new(0).compile_block(&indirect, &Linker::new(), &HashMap::new()).unwrap();
+        assert_eq!(
+            body,
+            vec![
+                0x01, 0x03, 0x7e, // locals: r1 -> 0, r2 -> 1, r4 -> 2
+                0x20, 0x02, 0xa7, 0x20, 0x00, 0x37, 0x03, 0x00, // i64.store [wrap(r4)], r1
+                0x20, 0x02, 0xa7, 0x35, 0x02, 0x00, 0x21, 0x01, // r2 = i64.load32_u [wrap(r4)]
+                0x0b,
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_use_relocated_function_indices() {
+        let mut linker = Linker::new();
+        linker.define_symbol("main".to_string(), 0x1000);
+
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Ret]);
+        blocks.insert(0x1010, vec![IRAp::Syscall(60), IRAp::Call(0x1000), IRAp::Jmp(0x1010)]);
+        let module = Compiler::new(0).compile(&blocks, &mut linker).unwrap();
+        wasmparser::validate(&module).unwrap();
+
+        // The syscall import is function 0, so main is function 1
+        let functions = linker.functions.clone();
+        let body = Compiler::new(0).compile_block(&blocks[&0x1010], &linker, &functions).unwrap();
+        assert_eq!(body, vec![0x00, 0x41, 0x3c, 0x10, 0x00, 0x10, 0x01, 0x10, 0x02, 0x0f, 0x0b]);
+    }
+
+    #[test]
+    fn start_section_runs_the_register_init() {
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Store(0x10, 1, IRWidth::W64), IRAp::Ret]);
+        blocks.insert(0x1010, vec![IRAp::Ret]);
+        let mut linker = Linker::new();
+        linker.resolve_imports(vec!["puts".to_string()]);
+        let module = Compiler::new(0).compile(&blocks, &mut linker).unwrap();
+        wasmparser::validate(&module).unwrap();
+
+        // One import and two blocks, so the init function is index 3
+        let start = wasmparser::Parser::new(0).parse_all(&module).find_map(|payload| match payload.unwrap() {
+            wasmparser::Payload::StartSection { func, .. } => Some(func),
+            _ => None,
+        });
+        assert_eq!(start, Some(3));
+
+        // The register file sits in the page after the data: memory.fill at 0x10000
+        assert_eq!(Compiler::init_body(0x1_0000), vec![0x00, 0x41, 0x80, 0x80, 0x04, 0x41, 0x00, 0x41, 0x80, 0x10, 0xfc, 0x0b, 0x00, 0x0b]);
+    }
+
+    #[test]
+    fn configured_memory_is_limited_and_exported() {
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Store(0x10, 1, IRWidth::W64), IRAp::Ret]);
+        let module = Compiler::new(0).with_memory(4, 256).compile(&blocks, &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+
+        let mut memories = Vec::new();
+        let mut exports = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(&module) {
+            match payload.unwrap() {
+                wasmparser::Payload::MemorySection(reader) => {
+                    for memory in reader {
+                        let memory = memory.unwrap();
+                        memories.push((memory.initial, memory.maximum));
+                    }
+                }
+                wasmparser::Payload::ExportSection(reader) => {
+                    for export in reader {
+                        exports.push(export.unwrap().name.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(memories, vec![(4, Some(256))]);
+        assert_eq!(exports, vec!["0x1000", MEMORY_EXPORT]);
+
+        // The minimum grows to fit the data and register file, but never past the maximum
+        let module = Compiler::new(0).with_memory(0, 256).compile(&blocks, &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+        assert!(Compiler::new(0).with_memory(1, 1).compile(&blocks, &mut Linker::new()).is_err());
+
+        // wasm32 memories are capped at 65536 pages
+        let module = Compiler::new(0).with_memory(1, 65536).compile(&blocks, &mut Linker::new()).unwrap();
+        wasmparser::validate(&module).unwrap();
+        assert!(Compiler::new(0).with_memory(1, 65537).compile(&blocks, &mut Linker::new()).is_err());
+    }
+
+    #[test]
+    fn huge_addresses_leave_no_room_for_the_register_file() {
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Load(0, 0xffff_ffff_ffff_fff0, IRWidth::W64), IRAp::Ret]);
+        let err = Compiler::new(0).compile(&blocks, &mut Linker::new()).unwrap_err();
+        assert!(err.contains("register file"), "{}", err);
+    }
+
+    #[test]
+    fn compile_output_is_reproducible() {
+        // Separately built maps get different hash seeds and iteration orders
+        let build = || {
+            (0..32u64)
+                .map(|i| (0x1000 + i * 0x10, vec![IRAp::Add(i as u8, 1, 2, IRWidth::W64), IRAp::Ret]))
+                .collect::<HashMap<_, _>>()
+        };
+        let compiler = Compiler::new(1);
+        let first = compiler.compile(&build(), &mut Linker::new()).unwrap();
+        let second = compiler.compile(&build(), &mut Linker::new()).unwrap();
+        assert_eq!(first, second);
+    }
+}
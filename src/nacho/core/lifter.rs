@@ -1,21 +1,72 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[cfg(feature = "real-decode")]
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, OpKind, Register};
+
+// Operand width of an arithmetic or memory op
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IRWidth {
+    W32,
+    W64,
+}
 
 // Intermediate Representation (IR) for machine code instructions
 #[derive(Debug, Clone, PartialEq)]
 pub enum IRAp {
-    Load(u8, u64), // reg, addr
-    Store(u64, u8), // addr, reg
-    Add(u8, u8, u8), // dest, src1, src2
-    Sub(u8, u8, u8),
-    Mul(u8, u8, u8),
-    Div(u8, u8, u8),
+    Load(u8, u64, IRWidth), // reg, addr, width
+    Store(u64, u8, IRWidth), // addr, reg, width
+    LoadIndirect(u8, u8, IRWidth), // reg, address reg, width
+    StoreIndirect(u8, u8, IRWidth), // address reg, reg, width
+    Mov(u8, u8, IRWidth), // dest, src, width
+    Add(u8, u8, u8, IRWidth), // dest, src1, src2, width
+    Sub(u8, u8, u8, IRWidth),
+    Mul(u8, u8, u8, IRWidth),
+    Div(u8, u8, u8, IRWidth),
     Jmp(u64), // target
-    Bz(u8, u64), // reg, target
+    Bz(u8, u64, u64), // reg, taken target, fallthrough (next instruction)
     Call(u64), // target
     Ret,
     Syscall(u32), // syscall_id
+    Const(u8, u64), // reg, value
+}
+
+// Textual form used by Lifter::dump. 64-bit registers print as rN and
+// 32-bit ones as wN
+impl fmt::Display for IRAp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = |reg: &u8, width: &IRWidth| match width {
+            IRWidth::W32 => format!("w{}", reg),
+            IRWidth::W64 => format!("r{}", reg),
+        };
+        match self {
+            IRAp::Load(reg, addr, w) => write!(f, "load {}, [{:#x}]", r(reg, w), addr),
+            IRAp::Store(addr, reg, w) => write!(f, "store [{:#x}], {}", addr, r(reg, w)),
+            IRAp::LoadIndirect(reg, a, w) => write!(f, "load {}, [r{}]", r(reg, w), a),
+            IRAp::StoreIndirect(a, reg, w) => write!(f, "store [r{}], {}", a, r(reg, w)),
+            IRAp::Mov(d, s, w) => write!(f, "mov {}, {}", r(d, w), r(s, w)),
+            IRAp::Add(d, a, b, w) => write!(f, "add {}, {}, {}", r(d, w), r(a, w), r(b, w)),
+            IRAp::Sub(d, a, b, w) => write!(f, "sub {}, {}, {}", r(d, w), r(a, w), r(b, w)),
+            IRAp::Mul(d, a, b, w) => write!(f, "mul {}, {}, {}", r(d, w), r(a, w), r(b, w)),
+            IRAp::Div(d, a, b, w) => write!(f, "div {}, {}, {}", r(d, w), r(a, w), r(b, w)),
+            IRAp::Jmp(target) => write!(f, "jmp {:#x}", target),
+            IRAp::Bz(reg, taken, fallthrough) => write!(f, "bz r{}, {:#x}, {:#x}", reg, taken, fallthrough),
+            IRAp::Call(target) => write!(f, "call {:#x}", target),
+            IRAp::Ret => write!(f, "ret"),
+            IRAp::Syscall(u32::MAX) => write!(f, "syscall ?"),
+            IRAp::Syscall(id) => write!(f, "syscall {}", id),
+            IRAp::Const(reg, value) => write!(f, "const r{}, {:#x}", reg, value),
+        }
+    }
 }
 
+// IR register holding ARM64 immediates, past x0-x30 and SP (31)
+const ARM64_SCRATCH: u8 = 32;
+
+// IR register set to zero wherever an ARM64 operand encoded as 31 means
+// XZR/WZR rather than SP
+const ARM64_ZERO: u8 = 33;
+
 pub struct Lifter {
     // Map of address to IR instructions
     pub blocks: HashMap<u64, Vec<IRAp>>,
@@ -29,52 +80,807 @@ impl Lifter {
     }
 
     // Lift x86_64 machine code into IR
+    // With the `real-decode` feature this goes through iced-x86; otherwise the
+    // byte-matching decoder below is used
     pub fn lift_x64(&mut self, binary: &[u8], entry_point: u64) -> Result<(), String> {
+        #[cfg(feature = "real-decode")]
+        {
+            self.lift_x64_decoded(binary, entry_point)
+        }
+        #[cfg(not(feature = "real-decode"))]
+        {
+            self.lift_x64_bytes(binary, entry_point)
+        }
+    }
+
+    // Candidate function starts in a stripped x86_64 blob: offsets of the
+    // `push rbp; mov rbp, rsp` prologue in either encoding of the mov
+    // (48 89 e5 / 48 8b ec). An endbr64 right before the prologue is
+    // included in the function. Each offset can be lifted with
+    // `lift_x64(&binary[offset..], base + offset)`
+    pub fn discover_entry_points(binary: &[u8]) -> Vec<u64> {
+        const PROLOGUES: [[u8; 4]; 2] = [[0x55, 0x48, 0x89, 0xe5], [0x55, 0x48, 0x8b, 0xec]];
+        const ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+
+        binary.windows(4)
+            .enumerate()
+            .filter(|(_, window)| PROLOGUES.contains(&[window[0], window[1], window[2], window[3]]))
+            .map(|(offset, _)| {
+                if offset >= 4 && binary[offset - 4..offset] == ENDBR64 {
+                    offset as u64 - 4
+                } else {
+                    offset as u64
+                }
+            })
+            .collect()
+    }
+
+    // Toy decoder that only recognizes a handful of fixed encodings
+    #[cfg_attr(feature = "real-decode", allow(dead_code))]
+    fn lift_x64_bytes(&mut self, binary: &[u8], entry_point: u64) -> Result<(), String> {
         let mut pc = entry_point;
-        let mut current_block = Vec::new();
+        let mut insns = Vec::new();
+        // Last immediate moved into eax/rax, used as the syscall number
+        let mut rax_imm: Option<u32> = None;
 
         // Simple simulated disassembler loop
         // In a real implementation, we would use a library like capstone
         let mut i = 0;
         while i < binary.len() {
             let byte = binary[i];
-            match byte {
+            let rel8 = |next: u64| next.wrapping_add_signed(binary[i + 1] as i8 as i64);
+            let imm32 = |at: usize| u32::from_le_bytes([binary[at], binary[at + 1], binary[at + 2], binary[at + 3]]);
+            let rel32 = |next: u64| next.wrapping_add_signed(imm32(i + 1) as i32 as i64);
+            // Bytes consumed by this instruction; pc and i advance together
+            let (op, len) = match byte {
                 0x55 => { // push rbp
-                    current_block.push(IRAp::Store(0, 0)); // Placeholder
-                    i += 1;
+                    (Some(IRAp::Store(0, 0, IRWidth::W64)), 1) // Placeholder
                 }
                 0x48 => { // REX.W
                     if i + 2 < binary.len() && binary[i+1] == 0x89 && binary[i+2] == 0xe5 {
                         // mov rbp, rsp
-                        current_block.push(IRAp::Add(0, 1, 0)); // Placeholder
-                        i += 3;
+                        (Some(IRAp::Mov(0, 1, IRWidth::W64)), 3) // Placeholder
+                    } else if i + 6 < binary.len() && binary[i+1] == 0xc7 && binary[i+2] == 0xc0 {
+                        // mov rax, imm32
+                        rax_imm = Some(imm32(i + 3));
+                        (None, 7)
+                    } else if i + 2 < binary.len() && matches!(binary[i+1], 0x01 | 0x29) && binary[i+2] >= 0xc0 {
+                        // add/sub r/m64, r64 with a register destination
+                        (Some(Self::x64_alu_rr(binary[i+1], binary[i+2], IRWidth::W64, &mut rax_imm)), 3)
                     } else {
-                        i += 1;
+                        (None, Self::x64_insn_len(&binary[i..]))
                     }
                 }
+                0x74 if i + 1 < binary.len() => { // je rel8, flags modelled as reg 0
+                    (Some(IRAp::Bz(0, rel8(pc + 2), pc + 2)), 2)
+                }
+                0xeb if i + 1 < binary.len() => { // jmp rel8
+                    (Some(IRAp::Jmp(rel8(pc + 2))), 2)
+                }
+                0x01 | 0x29 if i + 1 < binary.len() && binary[i+1] >= 0xc0 => { // add/sub r/m32, r32
+                    (Some(Self::x64_alu_rr(byte, binary[i+1], IRWidth::W32, &mut rax_imm)), 2)
+                }
+                0xb8 if i + 4 < binary.len() => { // mov eax, imm32
+                    rax_imm = Some(imm32(i + 1));
+                    (None, 5)
+                }
+                0x0f if i + 1 < binary.len() && binary[i+1] == 0x05 => { // syscall, result in rax
+                    (Some(IRAp::Syscall(rax_imm.take().unwrap_or(u32::MAX))), 2)
+                }
+                0xcd if i + 1 < binary.len() && binary[i+1] == 0x80 => { // int 0x80
+                    (Some(IRAp::Syscall(rax_imm.take().unwrap_or(u32::MAX))), 2)
+                }
+                0xe8 if i + 4 < binary.len() => { // call rel32, clobbers rax
+                    rax_imm = None;
+                    (Some(IRAp::Call(rel32(pc + 5))), 5)
+                }
+                0xe9 if i + 4 < binary.len() => { // jmp rel32
+                    (Some(IRAp::Jmp(rel32(pc + 5))), 5)
+                }
                 0xc3 => { // ret
-                    current_block.push(IRAp::Ret);
-                    self.blocks.insert(pc, current_block.clone());
-                    current_block.clear();
+                    (Some(IRAp::Ret), 1)
+                }
+                // Not lifted, but still skipped by its full length
+                _ => (None, Self::x64_insn_len(&binary[i..])),
+            };
+            insns.push((pc, op));
+            i += len;
+            pc += len as u64;
+        }
+
+        self.split_blocks(entry_point, insns);
+        Ok(())
+    }
+
+    // Register-to-register add (0x01) or sub (0x29) from its ModRM byte
+    fn x64_alu_rr(opcode: u8, modrm: u8, width: IRWidth, rax_imm: &mut Option<u32>) -> IRAp {
+        let dest = modrm & 7;
+        let src = (modrm >> 3) & 7;
+        if dest == 0 {
+            *rax_imm = None;
+        }
+        if opcode == 0x01 {
+            IRAp::Add(dest, dest, src, width)
+        } else {
+            IRAp::Sub(dest, dest, src, width)
+        }
+    }
+
+    // Length of an instruction the toy decoder doesn't lift, from its legacy
+    // prefixes, REX, opcode, ModRM/SIB/displacement and immediate. Unknown or
+    // truncated encodings fall back to a single byte
+    #[cfg_attr(feature = "real-decode", allow(dead_code))]
+    fn x64_insn_len(code: &[u8]) -> usize {
+        let mut i = 0;
+        let mut opsize16 = false;
+        while let Some(&b) = code.get(i) {
+            match b {
+                0x66 => opsize16 = true,
+                0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 | 0x67 | 0xf0 | 0xf2 | 0xf3 => {}
+                _ => break,
+            }
+            i += 1;
+        }
+        let mut rex_w = false;
+        if let Some(&b) = code.get(i).filter(|&&b| b & 0xf0 == 0x40) {
+            rex_w = b & 0x08 != 0;
+            i += 1;
+        }
+        let Some(&op) = code.get(i) else { return 1 };
+        i += 1;
+
+        // imm16/32 depending on operand size
+        let immz = if opsize16 { 2 } else { 4 };
+        let (has_modrm, imm) = if op == 0x0f {
+            let Some(&op2) = code.get(i) else { return 1 };
+            i += 1;
+            match op2 {
+                0x05 | 0x0b | 0x31 | 0xa2 => (false, 0), // syscall, ud2, rdtsc, cpuid
+                0x80..=0x8f => (false, 4),               // jcc rel32
+                0x38 => {
                     i += 1;
-                    // Heuristic: stop if we hit a ret and have no other known entry points
-                    // For now, just continue
+                    (true, 0)
                 }
-                // ... more x86 decoding logic ...
-                _ => {
+                0x3a => {
                     i += 1;
+                    (true, 1)
+                }
+                0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => (true, 1),
+                _ => (true, 0),
+            }
+        } else {
+            match op {
+                0x00..=0x3f => match op & 7 {
+                    0..=3 => (true, 0), // alu r/m, reg
+                    4 => (false, 1),    // alu al, imm8
+                    5 => (false, immz), // alu eax, imm
+                    _ => return 1,
+                },
+                0x50..=0x5f | 0x90..=0x99 | 0x9b..=0x9f | 0xa4..=0xa7 | 0xaa..=0xaf => (false, 0),
+                0x63 | 0x84..=0x8f | 0xd0..=0xd3 | 0xd8..=0xdf | 0xfe | 0xff => (true, 0),
+                0x68 | 0xa9 => (false, immz),
+                0x69 | 0x81 | 0xc7 => (true, immz),
+                0x6a | 0x70..=0x7f | 0xa8 | 0xcd | 0xe0..=0xe7 | 0xeb => (false, 1),
+                0x6b | 0x80 | 0x83 | 0xc0 | 0xc1 | 0xc6 => (true, 1),
+                0xa0..=0xa3 => (false, 8), // mov with a 64-bit moffs
+                0xb0..=0xb7 => (false, 1),
+                0xb8..=0xbf => (false, if rex_w { 8 } else { immz }),
+                0xc2 | 0xca => (false, 2),
+                0xc3 | 0xc9 | 0xcb | 0xcc | 0xec..=0xef | 0xf4 | 0xf5 | 0xf8..=0xfd => (false, 0),
+                0xc8 => (false, 3),
+                0xe8 | 0xe9 => (false, 4),
+                // test takes an immediate, the other group-3 ops don't
+                0xf6 | 0xf7 => {
+                    let reg = code.get(i).map_or(0, |m| (m >> 3) & 7);
+                    let size = if op == 0xf6 { 1 } else { immz };
+                    (true, if reg < 2 { size } else { 0 })
+                }
+                _ => return 1,
+            }
+        };
+
+        if has_modrm {
+            let Some(&modrm) = code.get(i) else { return 1 };
+            i += 1;
+            let (md, rm) = (modrm >> 6, modrm & 7);
+            if md != 3 && rm == 4 {
+                // SIB, with a disp32 when there is no base register
+                let Some(&sib) = code.get(i) else { return 1 };
+                i += 1;
+                if md == 0 && sib & 7 == 5 {
+                    i += 4;
                 }
             }
-            pc += 1;
+            i += match md {
+                0 if rm == 5 => 4, // rip-relative
+                1 => 1,
+                2 => 4,
+                _ => 0,
+            };
+        }
+        i += imm;
+        if i <= code.len() {
+            i
+        } else {
+            1
+        }
+    }
+
+    // Full x86_64 decode: instruction lengths come from iced-x86, and
+    // mov/add/sub/jcc/jmp/call/ret are mapped onto IR ops. Anything else is skipped
+    #[cfg(feature = "real-decode")]
+    fn lift_x64_decoded(&mut self, binary: &[u8], entry_point: u64) -> Result<(), String> {
+        let mut decoder = Decoder::with_ip(64, binary, entry_point, DecoderOptions::NONE);
+        let mut instr = Instruction::default();
+        let mut insns = Vec::new();
+        let mut rax_imm: Option<u32> = None;
+
+        while decoder.can_decode() {
+            decoder.decode_out(&mut instr);
+            if instr.is_invalid() {
+                return Err(format!("invalid x86_64 instruction at {:#x}", instr.ip()));
+            }
+            insns.push((instr.ip(), Self::map_x64(&instr, &mut rax_imm)));
         }
-        
+
+        self.split_blocks(entry_point, insns);
         Ok(())
     }
 
-    // Lift ARM64 machine code into IR
+    // Second lifting pass over (address, op) pairs from a decoder. Every
+    // jump/branch/call target starts a new block, and Jmp/Bz/Ret end one.
+    // A block cut short by a target has no terminator; it falls through to
+    // the block that follows
+    fn split_blocks(&mut self, entry_point: u64, insns: Vec<(u64, Option<IRAp>)>) {
+        let targets: HashSet<u64> = insns
+            .iter()
+            .filter_map(|(_, op)| match op {
+                Some(IRAp::Jmp(t)) | Some(IRAp::Bz(_, t, _)) | Some(IRAp::Call(t)) => Some(*t),
+                _ => None,
+            })
+            .collect();
+
+        let mut block_start = entry_point;
+        let mut current_block = Vec::new();
+        let mut after_terminator = false;
+
+        // An instruction lifted to several ops repeats its address; only the
+        // first of them can start a block
+        for (addr, op) in insns {
+            if after_terminator || (targets.contains(&addr) && addr != block_start) {
+                if !current_block.is_empty() {
+                    self.blocks.insert(block_start, std::mem::take(&mut current_block));
+                }
+                block_start = addr;
+                after_terminator = false;
+            }
+            if let Some(op) = op {
+                after_terminator = matches!(op, IRAp::Jmp(_) | IRAp::Bz(..) | IRAp::Ret);
+                current_block.push(op);
+            }
+        }
+
+        // Code that runs off the end without a ret still forms a block
+        if !current_block.is_empty() {
+            self.blocks.insert(block_start, current_block);
+        }
+    }
+
+    // Map a decoded instruction onto IR, or None if it has no IR equivalent.
+    // `rax_imm` carries the last immediate moved into eax/rax so syscalls can
+    // recover their number
+    #[cfg(feature = "real-decode")]
+    fn map_x64(instr: &Instruction, rax_imm: &mut Option<u32>) -> Option<IRAp> {
+        let reg = |r: Register| r.full_register().number() as u8;
+        let width = |r: Register| if r.size() == 8 { IRWidth::W64 } else { IRWidth::W32 };
+        let is_reg = |n: u32| instr.op_kind(n) == OpKind::Register;
+        let is_imm = matches!(
+            instr.op1_kind(),
+            OpKind::Immediate32 | OpKind::Immediate32to64 | OpKind::Immediate64
+        );
+
+        if instr.mnemonic() == Mnemonic::Mov && is_reg(0) && is_imm
+            && instr.op0_register().full_register() == Register::RAX
+        {
+            *rax_imm = u32::try_from(instr.immediate(1)).ok();
+            return None;
+        }
+        // Calls and syscalls leave their return value in rax
+        let writes_rax = matches!(instr.mnemonic(), Mnemonic::Call | Mnemonic::Syscall | Mnemonic::Int)
+            || (is_reg(0) && instr.op0_register().full_register() == Register::RAX);
+        let syscall = IRAp::Syscall(rax_imm.unwrap_or(u32::MAX));
+        if writes_rax {
+            *rax_imm = None;
+        }
+        // Absolute [disp] and RIP-relative operands resolve to a fixed address
+        let is_direct_mem = (instr.op0_kind() == OpKind::Memory || instr.op1_kind() == OpKind::Memory)
+            && instr.memory_index() == Register::None
+            && matches!(instr.memory_base(), Register::None | Register::RIP);
+
+        match instr.mnemonic() {
+            Mnemonic::Mov if is_reg(0) && is_reg(1) => {
+                let dest = instr.op0_register();
+                Some(IRAp::Mov(reg(dest), reg(instr.op1_register()), width(dest)))
+            }
+            Mnemonic::Mov if is_reg(0) && is_direct_mem => {
+                let dest = instr.op0_register();
+                Some(IRAp::Load(reg(dest), instr.memory_displacement64(), width(dest)))
+            }
+            Mnemonic::Mov if is_reg(1) && is_direct_mem => {
+                let src = instr.op1_register();
+                Some(IRAp::Store(instr.memory_displacement64(), reg(src), width(src)))
+            }
+            Mnemonic::Add if is_reg(0) && is_reg(1) => {
+                let dest = instr.op0_register();
+                Some(IRAp::Add(reg(dest), reg(dest), reg(instr.op1_register()), width(dest)))
+            }
+            Mnemonic::Sub if is_reg(0) && is_reg(1) => {
+                let dest = instr.op0_register();
+                Some(IRAp::Sub(reg(dest), reg(dest), reg(instr.op1_register()), width(dest)))
+            }
+            Mnemonic::Je if instr.op0_kind() == OpKind::NearBranch64 => {
+                // Flags are modelled as reg 0, as in the byte decoder
+                Some(IRAp::Bz(0, instr.near_branch_target(), instr.next_ip()))
+            }
+            Mnemonic::Jmp if instr.op0_kind() == OpKind::NearBranch64 => {
+                Some(IRAp::Jmp(instr.near_branch_target()))
+            }
+            Mnemonic::Call if instr.op0_kind() == OpKind::NearBranch64 => {
+                Some(IRAp::Call(instr.near_branch_target()))
+            }
+            Mnemonic::Ret => Some(IRAp::Ret),
+            Mnemonic::Syscall => Some(syscall),
+            Mnemonic::Int if instr.immediate8() == 0x80 => Some(syscall),
+            _ => None,
+        }
+    }
+
+    // Successor block addresses for every lifted block, taken from its last op.
+    // A Bz lists its taken target, then the fallthrough it recorded. Blocks
+    // without a terminator fall through to the next block by address
+    pub fn control_flow_graph(&self) -> HashMap<u64, Vec<u64>> {
+        let mut starts: Vec<u64> = self.blocks.keys().copied().collect();
+        starts.sort_unstable();
+
+        let mut cfg = HashMap::new();
+        for (idx, &start) in starts.iter().enumerate() {
+            let fallthrough = starts.get(idx + 1).copied();
+            let successors = match self.blocks[&start].last() {
+                Some(IRAp::Jmp(target)) => vec![*target],
+                Some(IRAp::Bz(_, taken, next)) if next == taken => vec![*taken],
+                Some(IRAp::Bz(_, taken, next)) => vec![*taken, *next],
+                Some(IRAp::Ret) => Vec::new(),
+                _ => fallthrough.into_iter().collect(),
+            };
+            cfg.insert(start, successors);
+        }
+        cfg
+    }
+
+    // Human-readable listing of every block, sorted by address
+    pub fn dump(&self) -> String {
+        let mut starts: Vec<&u64> = self.blocks.keys().collect();
+        starts.sort_unstable();
+
+        let mut out = String::new();
+        for start in starts {
+            out.push_str(&format!("{:#x}:\n", start));
+            for op in &self.blocks[start] {
+                out.push_str(&format!("    {}\n", op));
+            }
+        }
+        out
+    }
+
+    // Lift ARM64 machine code into IR. Every instruction is one little-endian
+    // 32-bit word; trailing bytes that don't fill a word are ignored
     pub fn lift_arm64(&mut self, binary: &[u8], entry_point: u64) -> Result<(), String> {
-        // Placeholder for ARM64 lifting logic
+        let mut pc = entry_point;
+        let mut insns = Vec::new();
+        // Last MOVZ into x8, which holds the Linux syscall number
+        let mut x8_imm: Option<u32> = None;
+
+        for word in binary.chunks_exact(4) {
+            let w = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            let rd = (w & 0x1f) as u8;
+            let rn = ((w >> 5) & 0x1f) as u8;
+            let rm = ((w >> 16) & 0x1f) as u8;
+            // sf for data processing, size bit 30 for loads/stores
+            let sf = if w & 0x80000000 != 0 { IRWidth::W64 } else { IRWidth::W32 };
+            let size = if w & 0x40000000 != 0 { IRWidth::W64 } else { IRWidth::W32 };
+            // imm26 for B/BL, scaled by 4 and sign-extended
+            let branch_target = pc.wrapping_add_signed((((w << 6) as i32) >> 4) as i64);
+
+            let op = match w {
+                // ADD/SUB (immediate), where register 31 is SP. A zero immediate
+                // is the MOV to/from SP alias; otherwise the optionally
+                // LSL #12-shifted imm12 goes through the scratch register
+                _ if w & 0x3f800000 == 0x11000000 => {
+                    let imm = ((w >> 10) & 0xfff) as u64;
+                    let imm = if w & 0x00400000 != 0 { imm << 12 } else { imm };
+                    if imm == 0 {
+                        Some(IRAp::Mov(rd, rn, sf))
+                    } else {
+                        insns.push((pc, Some(IRAp::Const(ARM64_SCRATCH, imm))));
+                        Some(if w & 0x40000000 == 0 {
+                            IRAp::Add(rd, rn, ARM64_SCRATCH, sf)
+                        } else {
+                            IRAp::Sub(rd, rn, ARM64_SCRATCH, sf)
+                        })
+                    }
+                }
+                // ADD/SUB (shifted register) with LSL #0 only; other shifts are
+                // left unlifted. Register 31 is XZR here, so it reads as zero
+                // and a write to it is dropped
+                _ if w & 0x3f200000 == 0x0b000000 && w & 0x00c0fc00 == 0 => {
+                    if rd == 31 {
+                        None
+                    } else {
+                        if rn == 31 || rm == 31 {
+                            insns.push((pc, Some(IRAp::Const(ARM64_ZERO, 0))));
+                        }
+                        let zr = |r: u8| if r == 31 { ARM64_ZERO } else { r };
+                        Some(if w & 0x40000000 == 0 {
+                            IRAp::Add(rd, zr(rn), zr(rm), sf)
+                        } else {
+                            IRAp::Sub(rd, zr(rn), zr(rm), sf)
+                        })
+                    }
+                }
+                // LDR/STR (unsigned offset), 32- and 64-bit: base register (31
+                // is SP) plus the scaled imm12, summed in the scratch register.
+                // For the transferred register 31 is XZR/WZR, so a load into it
+                // is dropped and a store writes zero
+                _ if w & 0xbf800000 == 0xb9000000 => {
+                    let is_load = w & 0x00400000 != 0;
+                    let scale = if size == IRWidth::W64 { 8 } else { 4 };
+                    let offset = ((w >> 10) & 0xfff) as u64 * scale;
+                    if is_load && rd == 31 {
+                        None
+                    } else {
+                        let addr = if offset == 0 {
+                            rn
+                        } else {
+                            insns.push((pc, Some(IRAp::Const(ARM64_SCRATCH, offset))));
+                            insns.push((pc, Some(IRAp::Add(ARM64_SCRATCH, rn, ARM64_SCRATCH, IRWidth::W64))));
+                            ARM64_SCRATCH
+                        };
+                        if is_load {
+                            Some(IRAp::LoadIndirect(rd, addr, size))
+                        } else if rd == 31 {
+                            insns.push((pc, Some(IRAp::Const(ARM64_ZERO, 0))));
+                            Some(IRAp::StoreIndirect(addr, ARM64_ZERO, size))
+                        } else {
+                            Some(IRAp::StoreIndirect(addr, rd, size))
+                        }
+                    }
+                }
+                // LDR (literal), 32- and 64-bit: imm19 words from pc, a fixed
+                // address
+                _ if w & 0xbf000000 == 0x18000000 => {
+                    let offset = ((((w << 8) as i32) >> 13) as i64) * 4;
+                    Some(IRAp::Load(rd, pc.wrapping_add_signed(offset), size))
+                }
+                // MOVZ with no shift: only tracked for the syscall number
+                _ if w & 0x7fe00000 == 0x52800000 => {
+                    if rd == 8 {
+                        x8_imm = Some((w >> 5) & 0xffff);
+                    }
+                    None
+                }
+                _ if w & 0xfc000000 == 0x14000000 => Some(IRAp::Jmp(branch_target)),
+                _ if w & 0xfc000000 == 0x94000000 => {
+                    x8_imm = None;
+                    Some(IRAp::Call(branch_target))
+                }
+                _ if w & 0xfffffc1f == 0xd65f0000 => Some(IRAp::Ret),
+                _ if w & 0xffe0001f == 0xd4000001 => Some(IRAp::Syscall(x8_imm.unwrap_or(u32::MAX))),
+                _ => None,
+            };
+            insns.push((pc, op));
+            pc += 4;
+        }
+
+        self.split_blocks(entry_point, insns);
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use IRWidth::{W32, W64};
+
+    #[cfg(feature = "real-decode")]
+    #[test]
+    fn decoded_call_resolves_rel32_target() {
+        // push rbp; mov rbp, rsp; call 0x2000; pop rbp; ret
+        let code = [0x55, 0x48, 0x89, 0xe5, 0xe8, 0xf7, 0x0f, 0x00, 0x00, 0x5d, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x1000).unwrap();
+
+        let block = &lifter.blocks[&0x1000];
+        assert!(block.contains(&IRAp::Call(0x2000)), "{:?}", block);
+        assert_eq!(block.last(), Some(&IRAp::Ret));
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn prologue_block_is_keyed_to_entry_point() {
+        // push rbp; mov rbp, rsp; ret
+        let code = [0x55, 0x48, 0x89, 0xe5, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x1000).unwrap();
+
+        assert_eq!(lifter.blocks.len(), 1);
+        assert_eq!(
+            lifter.blocks[&0x1000],
+            vec![IRAp::Store(0, 0, W64), IRAp::Mov(0, 1, W64), IRAp::Ret]
+        );
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn unknown_instruction_is_skipped_by_its_full_length() {
+        // add ecx, 0xe8; ret. Stepping one byte at a time would decode the
+        // immediate as a call that swallows the ret
+        let code = [0x81, 0xc1, 0xe8, 0x00, 0x00, 0x00, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x1000).unwrap();
+
+        assert_eq!(lifter.blocks.len(), 1);
+        assert_eq!(lifter.blocks[&0x1000], vec![IRAp::Ret]);
+    }
+
+    #[test]
+    fn insn_len_covers_modrm_sib_and_immediates() {
+        // mov dword [rax+rbx*4+0x10], 0x1234
+        assert_eq!(Lifter::x64_insn_len(&[0xc7, 0x44, 0x98, 0x10, 0x34, 0x12, 0x00, 0x00]), 8);
+        // mov rcx, imm64
+        assert_eq!(Lifter::x64_insn_len(&[0x48, 0xb9, 1, 2, 3, 4, 5, 6, 7, 8]), 10);
+        // lea rax, [rip+0x100]
+        assert_eq!(Lifter::x64_insn_len(&[0x48, 0x8d, 0x05, 0x00, 0x01, 0x00, 0x00]), 7);
+        // jne rel32
+        assert_eq!(Lifter::x64_insn_len(&[0x0f, 0x85, 0x10, 0x00, 0x00, 0x00]), 6);
+        // truncated mov eax, imm32 falls back to one byte
+        assert_eq!(Lifter::x64_insn_len(&[0xb8, 0x01]), 1);
+    }
+
+    #[test]
+    fn discovers_both_prologues_in_a_stripped_blob() {
+        // int3 padding; push rbp; mov rbp, rsp; ret; nop;
+        // endbr64; push rbp; mov rbp, rsp (8b ec form); ret
+        let code = [
+            0xcc, 0xcc, 0x55, 0x48, 0x89, 0xe5, 0xc3, 0x90,
+            0xf3, 0x0f, 0x1e, 0xfa, 0x55, 0x48, 0x8b, 0xec, 0xc3,
+        ];
+        let entries = Lifter::discover_entry_points(&code);
+        assert_eq!(entries, [2, 8]);
+
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code[2..], 0x1000 + 2).unwrap();
+        assert_eq!(lifter.blocks[&0x1002].last(), Some(&IRAp::Ret));
+        assert!(Lifter::discover_entry_points(&[0x55, 0x48, 0x89]).is_empty());
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn second_block_starts_after_previous_ret() {
+        // push rbp; ret; mov rbp, rsp; ret
+        let code = [0x55, 0xc3, 0x48, 0x89, 0xe5, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x400000).unwrap();
+
+        assert_eq!(lifter.blocks[&0x400000], vec![IRAp::Store(0, 0, W64), IRAp::Ret]);
+        assert_eq!(lifter.blocks[&0x400002], vec![IRAp::Mov(0, 1, W64), IRAp::Ret]);
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn trailing_block_without_ret_is_kept() {
+        // push rbp; ret; push rbp; mov rbp, rsp (falls off the end)
+        let code = [0x55, 0xc3, 0x55, 0x48, 0x89, 0xe5];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x2000).unwrap();
+
+        assert_eq!(lifter.blocks.len(), 2);
+        assert_eq!(lifter.blocks[&0x2002], vec![IRAp::Store(0, 0, W64), IRAp::Mov(0, 1, W64)]);
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn forward_branch_target_starts_a_new_block() {
+        // push rbp; je +1; push rbp; target: ret
+        let code = [0x55, 0x74, 0x01, 0x55, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x3000).unwrap();
+
+        assert_eq!(lifter.blocks[&0x3000], vec![IRAp::Store(0, 0, W64), IRAp::Bz(0, 0x3004, 0x3003)]);
+        assert_eq!(lifter.blocks[&0x3004], vec![IRAp::Ret]);
+        // The fallthrough path gets its own block between the two
+        assert_eq!(lifter.blocks[&0x3003], vec![IRAp::Store(0, 0, W64)]);
+        assert_eq!(lifter.blocks.len(), 3);
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn control_flow_graph_follows_branches_and_fallthrough() {
+        // push rbp; je +1; push rbp; target: ret
+        let code = [0x55, 0x74, 0x01, 0x55, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x3000).unwrap();
+
+        let cfg = lifter.control_flow_graph();
+        assert_eq!(cfg[&0x3000], vec![0x3004, 0x3003]);
+        assert_eq!(cfg[&0x3003], vec![0x3004]);
+        assert!(cfg[&0x3004].is_empty());
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn conditional_branch_records_taken_and_fallthrough() {
+        // je -2 (back to 0x5000); nop; ret
+        let code = [0x74, 0xfe, 0x90, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x5000).unwrap();
+        assert_eq!(lifter.blocks[&0x5000], vec![IRAp::Bz(0, 0x5000, 0x5002)]);
+
+        // A branch to the next instruction has one successor
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&[0x74, 0x00, 0xc3], 0x6000).unwrap();
+        assert_eq!(lifter.blocks[&0x6000], vec![IRAp::Bz(0, 0x6002, 0x6002)]);
+        assert_eq!(lifter.control_flow_graph()[&0x6000], vec![0x6002]);
+    }
+
+    #[test]
+    fn syscall_number_is_recovered_from_eax() {
+        // mov eax, 1; syscall
+        let code = [0xb8, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x4000).unwrap();
+        assert_eq!(lifter.blocks[&0x4000], vec![IRAp::Syscall(1)]);
+
+        // A bare syscall has no known number
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&[0x0f, 0x05], 0x4000).unwrap();
+        assert_eq!(lifter.blocks[&0x4000], vec![IRAp::Syscall(u32::MAX)]);
+    }
+
+    #[test]
+    fn rex_w_add_lifts_as_64_bit() {
+        // add rax, rbx; add eax, ebx; sub rcx, rdx
+        let code = [0x48, 0x01, 0xd8, 0x01, 0xd8, 0x48, 0x29, 0xd1];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x5000).unwrap();
+        assert_eq!(
+            lifter.blocks[&0x5000],
+            vec![IRAp::Add(0, 0, 3, W64), IRAp::Add(0, 0, 3, W32), IRAp::Sub(1, 1, 2, W64)]
+        );
+    }
+
+    #[cfg(not(feature = "real-decode"))]
+    #[test]
+    fn dump_lists_blocks_in_address_order() {
+        // push rbp; je +1; add eax, ebx; target: ret
+        let code = [0x55, 0x74, 0x02, 0x01, 0xd8, 0xc3];
+        let mut lifter = Lifter::new();
+        lifter.lift_x64(&code, 0x1000).unwrap();
+
+        assert_eq!(
+            lifter.dump(),
+            "0x1000:\n    store [0x0], r0\n    bz r0, 0x1005, 0x1003\n\
+             0x1003:\n    add w0, w0, w3\n\
+             0x1005:\n    ret\n"
+        );
+    }
+
+    #[test]
+    fn arm64_add_and_ret() {
+        // add x0, x1, x2; ret
+        let code = [0x20, 0x00, 0x02, 0x8b, 0xc0, 0x03, 0x5f, 0xd6];
+        let mut lifter = Lifter::new();
+        lifter.lift_arm64(&code, 0x8000).unwrap();
+        assert_eq!(lifter.blocks[&0x8000], vec![IRAp::Add(0, 1, 2, W64), IRAp::Ret]);
+    }
+
+    #[test]
+    fn arm64_shifted_register_forms() {
+        // add x0, x1, x2, lsl #3; neg x3, x4 (sub x3, xzr, x4);
+        // add xzr, x1, x2 (a write to XZR: no effect); ret
+        let code = [
+            0x20, 0x0c, 0x02, 0x8b,
+            0xe3, 0x03, 0x04, 0xcb,
+            0x3f, 0x00, 0x02, 0x8b,
+            0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        let mut lifter = Lifter::new();
+        lifter.lift_arm64(&code, 0x8000).unwrap();
+        assert_eq!(
+            lifter.blocks[&0x8000],
+            vec![IRAp::Const(ARM64_ZERO, 0), IRAp::Sub(3, ARM64_ZERO, 4, W64), IRAp::Ret]
+        );
+    }
+
+    #[test]
+    fn arm64_branches_loads_and_svc() {
+        // bl +8; ldr x3, #16; mov x8, #93; svc #0; ldr x4, [x0, #16]; ret
+        let code = [
+            0x02, 0x00, 0x00, 0x94,
+            0x83, 0x00, 0x00, 0x58,
+            0xa8, 0x0b, 0x80, 0xd2,
+            0x01, 0x00, 0x00, 0xd4,
+            0x04, 0x08, 0x40, 0xf9,
+            0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        let mut lifter = Lifter::new();
+        lifter.lift_arm64(&code, 0x8000).unwrap();
+
+        // The literal load reads pc + 16; the base-register load adds its
+        // offset to x0 at run time
+        assert_eq!(lifter.blocks[&0x8000], vec![IRAp::Call(0x8008), IRAp::Load(3, 0x8014, W64)]);
+        assert_eq!(
+            lifter.blocks[&0x8008],
+            vec![
+                IRAp::Syscall(93),
+                IRAp::Const(ARM64_SCRATCH, 16),
+                IRAp::Add(ARM64_SCRATCH, 0, ARM64_SCRATCH, W64),
+                IRAp::LoadIndirect(4, ARM64_SCRATCH, W64),
+                IRAp::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn arm64_base_register_loads_and_stores() {
+        // ldr w1, [x2]; str w4, [sp, #8]; str xzr, [x1, #24]; ldr xzr, [x0]; ret
+        let code = [
+            0x41, 0x00, 0x40, 0xb9,
+            0xe4, 0x0b, 0x00, 0xb9,
+            0x3f, 0x0c, 0x00, 0xf9,
+            0x1f, 0x00, 0x40, 0xf9,
+            0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        let mut lifter = Lifter::new();
+        lifter.lift_arm64(&code, 0x8000).unwrap();
+        assert_eq!(
+            lifter.blocks[&0x8000],
+            vec![
+                IRAp::LoadIndirect(1, 2, W32),
+                IRAp::Const(ARM64_SCRATCH, 8),
+                IRAp::Add(ARM64_SCRATCH, 31, ARM64_SCRATCH, W64),
+                IRAp::StoreIndirect(ARM64_SCRATCH, 4, W32),
+                IRAp::Const(ARM64_SCRATCH, 24),
+                IRAp::Add(ARM64_SCRATCH, 1, ARM64_SCRATCH, W64),
+                IRAp::Const(ARM64_ZERO, 0),
+                IRAp::StoreIndirect(ARM64_SCRATCH, ARM64_ZERO, W64),
+                IRAp::Ret,
+            ]
+        );
+        assert!(lifter.dump().contains("store [r32], w4"), "{}", lifter.dump());
+    }
+
+    #[test]
+    fn arm64_immediates_go_through_a_const() {
+        // add x0, x1, #16; sub w2, w3, #1, lsl #12; mov x29, sp; ret
+        let code = [
+            0x20, 0x40, 0x00, 0x91,
+            0x62, 0x04, 0x40, 0x51,
+            0xfd, 0x03, 0x00, 0x91,
+            0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        let mut lifter = Lifter::new();
+        lifter.lift_arm64(&code, 0x8000).unwrap();
+        assert_eq!(
+            lifter.blocks[&0x8000],
+            vec![
+                IRAp::Const(ARM64_SCRATCH, 16),
+                IRAp::Add(0, 1, ARM64_SCRATCH, W64),
+                IRAp::Const(ARM64_SCRATCH, 0x1000),
+                IRAp::Sub(2, 3, ARM64_SCRATCH, W32),
+                IRAp::Mov(29, 31, W64),
+                IRAp::Ret,
+            ]
+        );
+    }
+}
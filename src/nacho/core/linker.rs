@@ -1,10 +1,67 @@
 use std::collections::HashMap;
 
+use super::compiler::write_uleb128;
+use super::lifter::IRAp;
+
+// WASM module name every host import is resolved against
+pub const IMPORT_MODULE: &str = "env";
+
+// Import signatures take type indices from here on; the compiler's own block
+// type comes first in the type section
+pub const FIRST_IMPORT_TYPE: u32 = 1;
+
+// WASM value types usable in import signatures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ValType {
+    fn encode(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F32 => 0x7d,
+            ValType::F64 => 0x7c,
+        }
+    }
+}
+
+// Parameter and result types of an imported function
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl FuncType {
+    // Signature given to imports registered without one: (i32) -> ()
+    pub fn host_default() -> Self {
+        FuncType { params: vec![ValType::I32], results: Vec::new() }
+    }
+
+    // Type section entry: 0x60, params, results
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(0x60);
+        for types in [&self.params, &self.results] {
+            write_uleb128(out, types.len() as u64);
+            out.extend(types.iter().map(|t| t.encode()));
+        }
+    }
+}
+
 pub struct Linker {
     // Map of symbol names to their addresses or IDs
     pub symbols: HashMap<String, u64>,
     // External imports required by the binary (DLLs/SOs)
     pub imports: Vec<String>,
+    // Signatures from define_import; other imports use FuncType::host_default
+    pub signatures: HashMap<String, FuncType>,
+    // Function index of each lifted block, recorded by the last relocate
+    pub functions: HashMap<u64, u32>,
 }
 
 impl Linker {
@@ -12,6 +69,8 @@ impl Linker {
         Linker {
             symbols: HashMap::new(),
             imports: Vec::new(),
+            signatures: HashMap::new(),
+            functions: HashMap::new(),
         }
     }
 
@@ -20,24 +79,308 @@ impl Linker {
         self.symbols.insert(name, address);
     }
 
-    // Resolve dynamic imports by creating stubs
+    // Resolve dynamic imports by creating stubs. Names that are already
+    // registered keep their index, so repeated calls hand out stable indices
     pub fn resolve_imports(&mut self, required_imports: Vec<String>) -> HashMap<String, u32> {
         let mut resolved_map = HashMap::new();
-        
-        for (idx, import) in required_imports.iter().enumerate() {
-            self.imports.push(import.clone());
+
+        for import in required_imports {
             // In WASM, imports are indexed
-            resolved_map.insert(import.clone(), idx as u32);
+            let idx = match self.import_index(&import) {
+                Some(idx) => idx,
+                None => {
+                    self.imports.push(import.clone());
+                    (self.imports.len() - 1) as u32
+                }
+            };
+            resolved_map.insert(import, idx);
         }
 
         resolved_map
     }
 
-    // Generate the import section for the WASM module
+    // WASM function index of every lifted block: imports come first, then the
+    // blocks in address order, matching the order the compiler emits them
+    pub fn function_indices(&self, blocks: &HashMap<u64, Vec<IRAp>>) -> HashMap<u64, u32> {
+        let mut starts: Vec<u64> = blocks.keys().copied().collect();
+        starts.sort_unstable();
+        starts
+            .into_iter()
+            .enumerate()
+            .map(|(idx, addr)| (addr, (self.imports.len() + idx) as u32))
+            .collect()
+    }
+
+    // Relocation pass: resolve every Call/Jmp target to the function index of
+    // the block at that address. A target with no lifted block is an error,
+    // reported by symbol name when one is defined there
+    pub fn relocate(&mut self, blocks: &HashMap<u64, Vec<IRAp>>) -> Result<HashMap<u64, u32>, String> {
+        self.functions = self.function_indices(blocks);
+        let functions = &self.functions;
+        let mut fixups = HashMap::new();
+
+        for op in blocks.values().flatten() {
+            let (IRAp::Call(target) | IRAp::Jmp(target)) = op else { continue };
+            match functions.get(target) {
+                Some(&idx) => {
+                    fixups.insert(*target, idx);
+                }
+                None => {
+                    let name = self.symbols.iter().find(|(_, addr)| *addr == target).map(|(name, _)| name);
+                    return Err(match name {
+                        Some(name) => format!("unresolved target {} at {:#x}", name, target),
+                        None => format!("unresolved target {:#x}", target),
+                    });
+                }
+            }
+        }
+
+        Ok(fixups)
+    }
+
+    // Custom "name" section naming imports and every lifted function that has
+    // a symbol. When several symbols share an address the first by name wins
+    pub fn generate_name_section(&self) -> Vec<u8> {
+        let mut names: Vec<(u32, &str)> = self
+            .imports
+            .iter()
+            .enumerate()
+            .map(|(idx, import)| (idx as u32, import.as_str()))
+            .collect();
+        for (name, addr) in &self.symbols {
+            if let Some(&idx) = self.functions.get(addr) {
+                names.push((idx, name.as_str()));
+            }
+        }
+        names.sort_unstable();
+        names.dedup_by_key(|(idx, _)| *idx);
+
+        // Function names subsection (id 1): a name map sorted by index
+        let mut name_map = Vec::new();
+        write_uleb128(&mut name_map, names.len() as u64);
+        for (idx, name) in names {
+            write_uleb128(&mut name_map, idx as u64);
+            write_uleb128(&mut name_map, name.len() as u64);
+            name_map.extend_from_slice(name.as_bytes());
+        }
+
+        let mut contents = Vec::new();
+        write_uleb128(&mut contents, 4);
+        contents.extend_from_slice(b"name");
+        contents.push(0x01);
+        write_uleb128(&mut contents, name_map.len() as u64);
+        contents.extend(name_map);
+
+        let mut section = vec![0x00];
+        write_uleb128(&mut section, contents.len() as u64);
+        section.extend(contents);
+        section
+    }
+
+    // Table and element sections for indirect calls: a funcref table with one
+    // slot per entry, filled from slot 0 with the given function indices so
+    // `call_indirect` on slot i calls indices[i]. Both are empty without entries
+    pub fn build_function_table(&self, indices: Vec<u32>) -> (Vec<u8>, Vec<u8>) {
+        if indices.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        // Table Section: one funcref table, min size only
+        let mut table = Vec::new();
+        write_uleb128(&mut table, 1);
+        table.push(0x70); // funcref
+        table.push(0x00); // min only
+        write_uleb128(&mut table, indices.len() as u64);
+
+        // Element Section: one active segment for table 0 at offset 0
+        let mut elem = Vec::new();
+        write_uleb128(&mut elem, 1);
+        elem.push(0x00); // active, table 0, function indices
+        elem.extend_from_slice(&[0x41, 0x00, 0x0b]); // i32.const 0; end
+        write_uleb128(&mut elem, indices.len() as u64);
+        for idx in indices {
+            write_uleb128(&mut elem, idx as u64);
+        }
+
+        let mut table_section = vec![0x04];
+        write_uleb128(&mut table_section, table.len() as u64);
+        table_section.extend(table);
+
+        let mut elem_section = vec![0x09];
+        write_uleb128(&mut elem_section, elem.len() as u64);
+        elem_section.extend(elem);
+
+        (table_section, elem_section)
+    }
+
+    // Register an import with its signature and return its function index
+    pub fn define_import(&mut self, name: String, params: Vec<ValType>, results: Vec<ValType>) -> u32 {
+        let idx = self.resolve_imports(vec![name.clone()])[&name];
+        self.signatures.insert(name, FuncType { params, results });
+        idx
+    }
+
+    // Signature of a registered import
+    pub fn import_signature(&self, name: &str) -> FuncType {
+        self.signatures.get(name).cloned().unwrap_or_else(FuncType::host_default)
+    }
+
+    // Distinct import signatures in order of first use. Entry i has type
+    // index FIRST_IMPORT_TYPE + i
+    pub fn import_types(&self) -> Vec<FuncType> {
+        let mut types: Vec<FuncType> = Vec::new();
+        for import in &self.imports {
+            let signature = self.import_signature(import);
+            if !types.contains(&signature) {
+                types.push(signature);
+            }
+        }
+        types
+    }
+
+    // Type index of a registered import's signature
+    pub fn import_type_index(&self, name: &str) -> Option<u32> {
+        self.import_index(name)?;
+        let signature = self.import_signature(name);
+        let position = self.import_types().iter().position(|t| *t == signature)?;
+        Some(FIRST_IMPORT_TYPE + position as u32)
+    }
+
+    // Function index of a registered import; imports come first in the WASM
+    // function index space
+    pub fn import_index(&self, name: &str) -> Option<u32> {
+        self.imports.iter().position(|import| import == name).map(|idx| idx as u32)
+    }
+
+    // Generate the import section for the WASM module: every import is a
+    // function `env.<name>` typed by its signature. Empty when there are no imports
     pub fn generate_import_section(&self) -> Vec<u8> {
         let mut section = Vec::new();
-        // ... WASM binary encoding for imports ...
+        if self.imports.is_empty() {
+            return section;
+        }
+
+        let mut contents = Vec::new();
+        write_uleb128(&mut contents, self.imports.len() as u64);
+        for import in &self.imports {
+            for name in [IMPORT_MODULE, import.as_str()] {
+                write_uleb128(&mut contents, name.len() as u64);
+                contents.extend_from_slice(name.as_bytes());
+            }
+            contents.push(0x00); // func
+            write_uleb128(&mut contents, self.import_type_index(import).unwrap_or(FIRST_IMPORT_TYPE) as u64);
+        }
+
+        section.push(0x02);
+        write_uleb128(&mut section, contents.len() as u64);
+        section.extend(contents);
         section
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_section_encodes_each_import() {
+        let mut linker = Linker::new();
+        assert!(linker.generate_import_section().is_empty());
+
+        linker.resolve_imports(vec!["puts".to_string(), "exit".to_string()]);
+        let section = linker.generate_import_section();
+        let mut expected = vec![0x02, 0x17, 0x02];
+        expected.extend_from_slice(b"\x03env\x04puts\x00\x01");
+        expected.extend_from_slice(b"\x03env\x04exit\x00\x01");
+        assert_eq!(section, expected);
+    }
+
+    #[test]
+    fn distinct_signatures_get_distinct_type_indices() {
+        let mut linker = Linker::new();
+        assert_eq!(linker.define_import("malloc".to_string(), vec![ValType::I64], vec![ValType::I64]), 0);
+        assert_eq!(linker.define_import("read".to_string(), vec![ValType::I32, ValType::I64, ValType::I64], vec![ValType::I64]), 1);
+        linker.resolve_imports(vec!["exit".to_string()]);
+        assert_eq!(linker.define_import("free".to_string(), vec![ValType::I64], vec![ValType::I64]), 3);
+
+        assert_eq!(linker.import_type_index("malloc"), Some(1));
+        assert_eq!(linker.import_type_index("read"), Some(2));
+        assert_eq!(linker.import_type_index("exit"), Some(3));
+        assert_eq!(linker.import_type_index("free"), Some(1));
+        assert_eq!(linker.import_type_index("missing"), None);
+
+        let mut encoded = Vec::new();
+        linker.import_types()[1].encode(&mut encoded);
+        assert_eq!(encoded, vec![0x60, 0x03, 0x7f, 0x7e, 0x7e, 0x01, 0x7e]);
+
+        // The import section points each import at its own type
+        let section = linker.generate_import_section();
+        assert!(section.ends_with(b"\x03env\x04free\x00\x01"));
+        assert!(section.windows(7).any(|w| w == b"\x04read\x00\x02"));
+    }
+
+    #[test]
+    fn calls_relocate_to_function_indices() {
+        let mut linker = Linker::new();
+        linker.resolve_imports(vec!["puts".to_string()]);
+        linker.define_symbol("main".to_string(), 0x1000);
+        linker.define_symbol("helper".to_string(), 0x3000);
+
+        let mut blocks = HashMap::new();
+        blocks.insert(0x2000, vec![IRAp::Call(0x1000), IRAp::Ret]);
+        blocks.insert(0x1000, vec![IRAp::Jmp(0x2000)]);
+
+        let fixups = linker.relocate(&blocks).unwrap();
+        assert_eq!(fixups[&0x1000], 1);
+        assert_eq!(fixups[&0x2000], 2);
+
+        blocks.insert(0x2000, vec![IRAp::Call(0x3000)]);
+        assert_eq!(linker.relocate(&blocks).unwrap_err(), "unresolved target helper at 0x3000");
+    }
+
+    #[test]
+    fn name_section_names_imports_and_symbols() {
+        let mut linker = Linker::new();
+        linker.resolve_imports(vec!["puts".to_string()]);
+        linker.define_symbol("main".to_string(), 0x1000);
+        linker.define_symbol("unlifted".to_string(), 0x9000);
+
+        let mut blocks = HashMap::new();
+        blocks.insert(0x1000, vec![IRAp::Ret]);
+        blocks.insert(0x2000, vec![IRAp::Ret]);
+        linker.relocate(&blocks).unwrap();
+
+        let section = linker.generate_name_section();
+        let mut expected = vec![0x00, 0x14, 0x04];
+        expected.extend_from_slice(b"name");
+        expected.extend_from_slice(&[0x01, 0x0d, 0x02]);
+        expected.extend_from_slice(b"\x00\x04puts");
+        expected.extend_from_slice(b"\x01\x04main");
+        assert_eq!(section, expected);
+    }
+
+    #[test]
+    fn function_table_holds_every_entry() {
+        let linker = Linker::new();
+        assert_eq!(linker.build_function_table(Vec::new()), (Vec::new(), Vec::new()));
+
+        let (table, elem) = linker.build_function_table(vec![3, 1, 200]);
+        // One funcref table whose min size is the entry count
+        assert_eq!(table, vec![0x04, 0x04, 0x01, 0x70, 0x00, 0x03]);
+        // One active segment at offset 0 listing the indices (200 as uleb128)
+        assert_eq!(elem, vec![0x09, 0x0a, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, 0x03, 0x01, 0xc8, 0x01]);
+    }
+
+    #[test]
+    fn repeated_resolution_reuses_indices() {
+        let mut linker = Linker::new();
+        let first = linker.resolve_imports(vec!["read".to_string(), "write".to_string()]);
+        let second = linker.resolve_imports(vec!["write".to_string(), "open".to_string(), "open".to_string()]);
+
+        assert_eq!(linker.imports, vec!["read", "write", "open"]);
+        assert_eq!(first["write"], 1);
+        assert_eq!(second["write"], 1);
+        assert_eq!(second["open"], 2);
+    }
+}
@@ -0,0 +1,37 @@
+use super::compiler::Compiler;
+use super::lifter::Lifter;
+use super::linker::Linker;
+
+// End-to-end pipeline: lift x86_64 machine code starting at `entry`, then
+// optimize and compile every lifted block into a WASM module. The linker
+// supplies the import, relocation and name sections along the way
+pub fn transpile(binary: &[u8], entry: u64, opt_level: u8) -> Result<Vec<u8>, String> {
+    let mut lifter = Lifter::new();
+    lifter.lift_x64(binary, entry)?;
+
+    let mut linker = Linker::new();
+    Compiler::new(opt_level).compile(&lifter.blocks, &mut linker)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prologue_and_ret_transpile_to_a_valid_module() {
+        // push rbp; mov rbp, rsp; ret
+        let code = [0x55, 0x48, 0x89, 0xe5, 0xc3];
+        for opt_level in 0..=2 {
+            let module = transpile(&code, 0x1000, opt_level).unwrap();
+            wasmparser::validate(&module).unwrap();
+        }
+    }
+
+    #[test]
+    fn unresolved_call_targets_fail_to_transpile() {
+        // call 0x2000 with nothing lifted there
+        let code = [0xe8, 0xfb, 0x0f, 0x00, 0x00, 0xc3];
+        assert!(transpile(&code, 0x1000, 0).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use wasm_bindgen::prelude::*;
-use flate2::write::{GzEncoder, GzDecoder};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use flate2::Compression as GzCompression;
-use std::io::Write;
+use std::io::{Read, Write};
 
 /// Compression algorithm types
 #[wasm_bindgen]
@@ -10,6 +11,7 @@ pub enum Algorithm {
     Gzip = 0,
     Zstd = 1,
     Lz4 = 2,
+    Brotli = 3,
 }
 
 /// Compress data using the specified algorithm
@@ -19,26 +21,327 @@ pub fn compress(data: &[u8], algorithm: Algorithm, level: u8) -> Result<Vec<u8>,
         Algorithm::Gzip => compress_gzip(data, level),
         Algorithm::Zstd => compress_zstd(data, level),
         Algorithm::Lz4 => compress_lz4(data),
+        Algorithm::Brotli => compress_brotli(data, level),
     }
 }
 
-/// Decompress data using the specified algorithm
+/// Compress, then decompress the result in-process and compare it with
+/// `data`, failing instead of returning output that doesn't round-trip.
+/// Roughly doubles the CPU cost; meant for archival writes.
+#[wasm_bindgen]
+pub fn compress_verified(data: &[u8], algorithm: Algorithm, level: u8) -> Result<Vec<u8>, JsValue> {
+    let compressed = compress(data, algorithm, level)?;
+    verify_round_trip(data, &compressed, |compressed| {
+        decompress(compressed, algorithm).map_err(|e| e.as_string().unwrap_or_default())
+    }).map_err(|e| JsValue::from_str(&e))?;
+    Ok(compressed)
+}
+
+fn verify_round_trip<F>(original: &[u8], compressed: &[u8], decompress: F) -> Result<(), String>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>, String>,
+{
+    let restored = decompress(compressed).map_err(|e| format!("Verification failed: {}", e))?;
+    if restored.len() != original.len() {
+        return Err(format!(
+            "Verification failed: decompressed to {} bytes, expected {}",
+            restored.len(), original.len()
+        ));
+    }
+    if let Some(offset) = restored.iter().zip(original).position(|(a, b)| a != b) {
+        return Err(format!("Verification failed: output differs at byte {}", offset));
+    }
+    Ok(())
+}
+
+/// Compress many buffers in one call, stopping at the first failure
+#[wasm_bindgen]
+pub fn compress_batch(items: Vec<js_sys::Uint8Array>, algorithm: Algorithm, level: u8) -> Result<Vec<js_sys::Uint8Array>, JsValue> {
+    let items: Vec<Vec<u8>> = items.iter().map(|item| item.to_vec()).collect();
+    let compressed = compress_all(&items, algorithm, level).map_err(|e| JsValue::from_str(&e))?;
+    Ok(compressed.iter().map(|c| js_sys::Uint8Array::from(c.as_slice())).collect())
+}
+
+fn compress_all(items: &[Vec<u8>], algorithm: Algorithm, level: u8) -> Result<Vec<Vec<u8>>, String> {
+    items.iter()
+        .enumerate()
+        .map(|(index, item)| {
+            compress(item, algorithm, level).map_err(|e| format!(
+                "Batch item {} failed: {}",
+                index,
+                e.as_string().unwrap_or_default()
+            ))
+        })
+        .collect()
+}
+
+/// Output of `compress_to_budget`: the chosen level and its compressed bytes
+#[wasm_bindgen(getter_with_clone)]
+pub struct BudgetResult {
+    pub level: u8,
+    pub data: Vec<u8>,
+}
+
+/// Compress at the lowest level whose output fits within `max_bytes`.
+/// LZ4 has no levels, so it's compressed once and checked against the budget.
+#[wasm_bindgen]
+pub fn compress_to_budget(data: &[u8], algorithm: Algorithm, max_bytes: usize) -> Result<BudgetResult, JsValue> {
+    fit_to_budget(data, algorithm, max_bytes).map_err(|e| JsValue::from_str(&e))
+}
+
+fn fit_to_budget(data: &[u8], algorithm: Algorithm, max_bytes: usize) -> Result<BudgetResult, String> {
+    let compress = |level| compress(data, algorithm, level).map_err(|e| e.as_string().unwrap_or_default());
+    let (min_level, max_level) = algorithm.level_range();
+
+    let mut best = compress(max_level)?;
+    if best.len() > max_bytes {
+        return Err(format!(
+            "Cannot fit {} bytes into {} bytes (smallest output is {} bytes)",
+            data.len(), max_bytes, best.len()
+        ));
+    }
+
+    // Higher levels generally produce smaller output, so search for the
+    // lowest level that still fits
+    let mut best_level = max_level;
+    let (mut lo, mut hi) = (min_level, max_level);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let candidate = compress(mid)?;
+        if candidate.len() <= max_bytes {
+            best = candidate;
+            best_level = mid;
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok(BudgetResult { level: best_level, data: best })
+}
+
+/// Decompress data using the specified algorithm. LZ4 data is either the
+/// size-prefixed block `compress` produces or an LZ4 frame.
 #[wasm_bindgen]
 pub fn decompress(data: &[u8], algorithm: Algorithm) -> Result<Vec<u8>, JsValue> {
     match algorithm {
         Algorithm::Gzip => decompress_gzip(data),
         Algorithm::Zstd => decompress_zstd(data),
         Algorithm::Lz4 => decompress_lz4(data),
+        Algorithm::Brotli => decompress_brotli(data),
     }
 }
 
-fn compress_gzip(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
-    let compression_level = match level {
-        0..=9 => GzCompression::new(level as u32),
-        _ => GzCompression::default(),
+/// Decompress into a caller-provided buffer instead of a fresh allocation,
+/// returning the number of bytes written. Fails if the decompressed data
+/// does not fit in `out`; bytes past the returned length are unspecified.
+#[wasm_bindgen]
+pub fn decompress_into(data: &[u8], algorithm: Algorithm, out: &mut [u8]) -> Result<usize, JsValue> {
+    decompress_to_slice(data, algorithm, out).map_err(|e| JsValue::from_str(&e))
+}
+
+fn decompress_to_slice(data: &[u8], algorithm: Algorithm, out: &mut [u8]) -> Result<usize, String> {
+    match algorithm {
+        Algorithm::Gzip => read_to_slice(GzDecoder::new(data), out, "Gzip"),
+        Algorithm::Zstd => zstd::bulk::decompress_to_buffer(data, out)
+            .map_err(|e| format!("Zstd decompression failed: {}", e)),
+        Algorithm::Lz4 if data.starts_with(&LZ4_FRAME_MAGIC) => {
+            let decoder = lz4::Decoder::new(data)
+                .map_err(|e| format!("LZ4 decompression failed: {}", e))?;
+            read_to_slice(decoder, out, "LZ4")
+        }
+        Algorithm::Lz4 => {
+            if data.len() < 4 {
+                return Err("LZ4 data is missing its size prefix".to_string());
+            }
+            let (prefix, block) = data.split_at(4);
+            let original_size = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+            if original_size as usize > out.len() {
+                return Err(format!("Output buffer too small: need {} bytes, have {}", original_size, out.len()));
+            }
+            lz4::block::decompress_to_buffer(block, Some(original_size as i32), out)
+                .map_err(|e| format!("LZ4 decompression failed: {}", e))
+        }
+        Algorithm::Brotli => read_to_slice(brotli::Decompressor::new(data, 4096), out, "Brotli"),
+    }
+}
+
+/// Fill `out` from a decoder, failing if the decoder has bytes left over
+fn read_to_slice(mut reader: impl Read, out: &mut [u8], name: &str) -> Result<usize, String> {
+    let mut written = 0;
+    while written < out.len() {
+        match reader.read(&mut out[written..]) {
+            Ok(0) => return Ok(written),
+            Ok(n) => written += n,
+            Err(e) => return Err(format!("{} decompression failed: {}", name, e)),
+        }
+    }
+    let mut probe = [0u8; 1];
+    match reader.read(&mut probe) {
+        Ok(0) => Ok(written),
+        Ok(_) => Err(format!("Output buffer too small: more than {} bytes", out.len())),
+        Err(e) => Err(format!("{} decompression failed: {}", name, e)),
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+/// Identify compressed data by its magic bytes.
+///
+/// Only framed formats carry a header: gzip, zstd and the LZ4 frame format.
+/// What `compress` produces for `Algorithm::Lz4` starts with the input size
+/// as a little-endian u32 rather than a magic, so it usually goes undetected,
+/// but a size whose first bytes match a magic is misdetected: an input of
+/// 35615 + k * 65536 bytes starts with `1f 8b` and is reported as gzip.
+#[wasm_bindgen]
+pub fn detect_algorithm(data: &[u8]) -> Option<Algorithm> {
+    if data.starts_with(&GZIP_MAGIC) {
+        Some(Algorithm::Gzip)
+    } else if data.starts_with(&ZSTD_MAGIC) {
+        Some(Algorithm::Zstd)
+    } else if data.starts_with(&LZ4_FRAME_MAGIC) {
+        Some(Algorithm::Lz4)
+    } else {
+        None
+    }
+}
+
+/// Decompress data whose algorithm is identified by `detect_algorithm`
+#[wasm_bindgen]
+pub fn decompress_auto(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    match detect_algorithm(data) {
+        Some(algorithm) => decompress(data, algorithm),
+        None => Err(JsValue::from_str("Unrecognized compression format")),
+    }
+}
+
+/// Magic at the start of `compress_framed` output
+const FRAME_MAGIC: [u8; 4] = *b"BLMZ";
+/// Magic, algorithm byte, then the original length as a little-endian u64
+const FRAME_HEADER_LEN: usize = 4 + 1 + 8;
+
+impl Algorithm {
+    /// Lowest and highest accepted level; LZ4 has no levels
+    fn level_range(self) -> (u8, u8) {
+        match self {
+            Algorithm::Gzip => (0, 9),
+            Algorithm::Zstd => (1, 22),
+            Algorithm::Lz4 => (0, 0),
+            Algorithm::Brotli => (0, 11),
+        }
+    }
+
+    /// Inverse of `algorithm as u8`
+    fn from_byte(byte: u8) -> Option<Algorithm> {
+        match byte {
+            0 => Some(Algorithm::Gzip),
+            1 => Some(Algorithm::Zstd),
+            2 => Some(Algorithm::Lz4),
+            3 => Some(Algorithm::Brotli),
+            _ => None,
+        }
+    }
+}
+
+/// Compress with a self-describing header recording the algorithm and the
+/// original length, so the blob can be stored without separate metadata.
+/// Read it back with `decompress_framed`.
+#[wasm_bindgen]
+pub fn compress_framed(data: &[u8], algorithm: Algorithm, level: u8) -> Result<Vec<u8>, JsValue> {
+    let body = compress(data, algorithm, level)?;
+    let mut output = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    output.extend_from_slice(&FRAME_MAGIC);
+    output.push(algorithm as u8);
+    output.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    output.extend_from_slice(&body);
+    Ok(output)
+}
+
+/// Decompress `compress_framed` output with the algorithm named in its
+/// header, checking the result has the recorded length
+#[wasm_bindgen]
+pub fn decompress_framed(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let (algorithm, original_len, body) = read_frame_header(data).map_err(|e| JsValue::from_str(&e))?;
+    let output = decompress(body, algorithm)?;
+    check_frame_len(&output, original_len).map_err(|e| JsValue::from_str(&e))?;
+    Ok(output)
+}
+
+/// Output of `compress_best`: the algorithm that won and its output as a
+/// `compress_framed` blob
+#[wasm_bindgen(getter_with_clone)]
+pub struct BestResult {
+    pub algorithm: Algorithm,
+    pub data: Vec<u8>,
+}
+
+/// Compress with gzip, zstd and LZ4 and keep the smallest output, for when
+/// only the final size matters. `level` is clamped into each algorithm's
+/// range and ties go to the earlier algorithm. The output is framed, so
+/// `decompress_framed` reads it without being told the algorithm.
+#[wasm_bindgen]
+pub fn compress_best(data: &[u8], level: u8) -> Result<BestResult, JsValue> {
+    let framed = |algorithm: Algorithm| {
+        let (min_level, max_level) = algorithm.level_range();
+        compress_framed(data, algorithm, level.clamp(min_level, max_level))
     };
-    
-    let mut encoder = GzEncoder::new(Vec::new(), compression_level);
+
+    let mut best = BestResult { algorithm: Algorithm::Gzip, data: framed(Algorithm::Gzip)? };
+    for algorithm in [Algorithm::Zstd, Algorithm::Lz4] {
+        let candidate = framed(algorithm)?;
+        if candidate.len() < best.data.len() {
+            best = BestResult { algorithm, data: candidate };
+        }
+    }
+    Ok(best)
+}
+
+fn read_frame_header(data: &[u8]) -> Result<(Algorithm, u64, &[u8]), String> {
+    if data.len() < FRAME_HEADER_LEN || !data.starts_with(&FRAME_MAGIC) {
+        return Err("Not framed compressed data".to_string());
+    }
+    let algorithm = Algorithm::from_byte(data[4])
+        .ok_or_else(|| format!("Unknown algorithm byte {} in frame header", data[4]))?;
+    let mut len = [0; 8];
+    len.copy_from_slice(&data[5..FRAME_HEADER_LEN]);
+    Ok((algorithm, u64::from_le_bytes(len), &data[FRAME_HEADER_LEN..]))
+}
+
+fn check_frame_len(output: &[u8], original_len: u64) -> Result<(), String> {
+    if output.len() as u64 != original_len {
+        return Err(format!(
+            "Framed data decompressed to {} bytes, header says {}",
+            output.len(), original_len
+        ));
+    }
+    Ok(())
+}
+
+fn gzip_level(level: u8) -> Result<GzCompression, String> {
+    if level > 9 {
+        return Err(format!("gzip level must be 0-9, got {}", level));
+    }
+    Ok(GzCompression::new(level as u32))
+}
+
+fn zstd_level(level: u8) -> Result<i32, String> {
+    if !(1..=22).contains(&level) {
+        return Err(format!("zstd level must be 1-22, got {}", level));
+    }
+    Ok(level as i32)
+}
+
+fn brotli_level(level: u8) -> Result<u32, String> {
+    if level > 11 {
+        return Err(format!("brotli level must be 0-11, got {}", level));
+    }
+    Ok(level as u32)
+}
+
+fn compress_gzip(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
+    let mut encoder = GzEncoder::new(Vec::new(), gzip_level(level)?);
     encoder.write_all(data)
         .map_err(|e| JsValue::from_str(&format!("Gzip compression failed: {}", e)))?;
     
@@ -47,17 +350,16 @@ fn compress_gzip(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
 }
 
 fn decompress_gzip(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    let mut decoder = GzDecoder::new(Vec::new());
-    decoder.write_all(data)
+    let mut decoder = GzDecoder::new(data);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)
         .map_err(|e| JsValue::from_str(&format!("Gzip decompression failed: {}", e)))?;
-    
-    decoder.finish()
-        .map_err(|e| JsValue::from_str(&format!("Gzip finalization failed: {}", e)))
+
+    Ok(output)
 }
 
 fn compress_zstd(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
-    let level = level.min(22).max(1) as i32; // zstd levels: 1-22
-    zstd::encode_all(data, level)
+    zstd::encode_all(data, zstd_level(level)?)
         .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))
 }
 
@@ -66,17 +368,199 @@ fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, JsValue> {
         .map_err(|e| JsValue::from_str(&format!("Zstd decompression failed: {}", e)))
 }
 
+/// Compress with zstd spread over `workers` compression threads, which
+/// pays off on large (10MB+) buffers in a threaded wasm build. The output is
+/// still a single standard zstd frame that `decompress` reads like any other.
+/// Without the `threads` feature, or with fewer than two workers, this is
+/// plain single-threaded compression.
+#[wasm_bindgen]
+pub fn compress_zstd_mt(data: &[u8], level: u8, workers: u32) -> Result<Vec<u8>, JsValue> {
+    let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), zstd_level(level)?)
+        .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))?;
+    set_zstd_workers(&mut encoder, workers);
+    encoder.write_all(data)
+        .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))?;
+    encoder.finish()
+        .map_err(|e| JsValue::from_str(&format!("Zstd finalization failed: {}", e)))
+}
+
+/// Switch the encoder to multithreaded mode. If libzstd rejects the worker
+/// count (e.g. it was built without thread support) the encoder stays
+/// single-threaded.
+#[cfg(feature = "threads")]
+fn set_zstd_workers(encoder: &mut zstd::stream::write::Encoder<'static, Vec<u8>>, workers: u32) {
+    if workers > 1 {
+        let _ = encoder.multithread(workers);
+    }
+}
+
+#[cfg(not(feature = "threads"))]
+fn set_zstd_workers(_encoder: &mut zstd::stream::write::Encoder<'static, Vec<u8>>, _workers: u32) {}
+
+/// Train a zstd dictionary from sample payloads that resemble the data
+/// to be compressed later
+#[wasm_bindgen]
+pub fn train_zstd_dictionary(samples: Vec<js_sys::Uint8Array>, dict_size: usize) -> Result<Vec<u8>, JsValue> {
+    let samples: Vec<Vec<u8>> = samples.iter().map(|s| s.to_vec()).collect();
+    train_dictionary(&samples, dict_size).map_err(|e| JsValue::from_str(&e))
+}
+
+fn train_dictionary(samples: &[Vec<u8>], dict_size: usize) -> Result<Vec<u8>, String> {
+    zstd::dict::from_samples(samples, dict_size)
+        .map_err(|e| format!("Zstd dictionary training failed: {}", e))
+}
+
+/// Compress with zstd using a dictionary from `train_zstd_dictionary`
+#[wasm_bindgen]
+pub fn compress_zstd_with_dict(data: &[u8], dict: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(zstd_level(level)?, dict)
+        .map_err(|e| JsValue::from_str(&format!("Zstd dictionary load failed: {}", e)))?;
+    compressor.compress(data)
+        .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))
+}
+
+/// Decompress zstd data produced with the same dictionary
+#[wasm_bindgen]
+pub fn decompress_zstd_with_dict(data: &[u8], dict: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(data, dict)
+        .map_err(|e| JsValue::from_str(&format!("Zstd dictionary load failed: {}", e)))?;
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)
+        .map_err(|e| JsValue::from_str(&format!("Zstd decompression failed: {}", e)))?;
+
+    Ok(output)
+}
+
+// LZ4 blocks don't record their decompressed size, so it's stored in front
+// of the block as a little-endian u32
 fn compress_lz4(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    lz4::block::compress(data, None, true)
-        .map_err(|e| JsValue::from_str(&format!("LZ4 compression failed: {}", e)))
+    let original_size = u32::try_from(data.len())
+        .map_err(|_| JsValue::from_str("LZ4 input exceeds 4GB"))?;
+    let block = lz4::block::compress(data, None, false)
+        .map_err(|e| JsValue::from_str(&format!("LZ4 compression failed: {}", e)))?;
+
+    let mut output = Vec::with_capacity(4 + block.len());
+    output.extend_from_slice(&original_size.to_le_bytes());
+    output.extend_from_slice(&block);
+    Ok(output)
 }
 
+// Also accepts an LZ4 frame. A size prefix that reads as the frame magic
+// would be an input of 407708164 bytes.
 fn decompress_lz4(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    lz4::block::decompress(data, None)
+    if data.starts_with(&LZ4_FRAME_MAGIC) {
+        return decompress_lz4_frame(data);
+    }
+    if data.len() < 4 {
+        return Err(JsValue::from_str("LZ4 data is missing its size prefix"));
+    }
+    let (prefix, block) = data.split_at(4);
+    let original_size = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+
+    lz4::block::decompress(block, Some(original_size as i32))
         .map_err(|e| JsValue::from_str(&format!("LZ4 decompression failed: {}", e)))
 }
 
-/// Get compression ratio (compressed_size / original_size)
+enum StreamEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+/// Incremental compressor for inputs too large to pass in one call.
+/// Feed chunks with `write` and collect the bytes it returns, then append
+/// the output of `finish`. Supports gzip, zstd and brotli.
+#[wasm_bindgen]
+pub struct StreamingCompressor {
+    encoder: Option<StreamEncoder>,
+}
+
+#[wasm_bindgen]
+impl StreamingCompressor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(algorithm: Algorithm, level: u8) -> Result<StreamingCompressor, JsValue> {
+        let encoder = match algorithm {
+            Algorithm::Gzip => StreamEncoder::Gzip(GzEncoder::new(Vec::new(), gzip_level(level)?)),
+            Algorithm::Zstd => StreamEncoder::Zstd(
+                zstd::stream::write::Encoder::new(Vec::new(), zstd_level(level)?)
+                    .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))?,
+            ),
+            Algorithm::Lz4 => return Err(JsValue::from_str("LZ4 does not support streaming compression")),
+            Algorithm::Brotli => StreamEncoder::Brotli(Box::new(
+                brotli::CompressorWriter::new(Vec::new(), 4096, brotli_level(level)?, 22),
+            )),
+        };
+        Ok(Self { encoder: Some(encoder) })
+    }
+
+    /// Compress a chunk, returning whatever compressed bytes are ready
+    pub fn write(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsValue> {
+        match self.encoder.as_mut() {
+            Some(StreamEncoder::Gzip(encoder)) => {
+                encoder.write_all(chunk)
+                    .map_err(|e| JsValue::from_str(&format!("Gzip compression failed: {}", e)))?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Some(StreamEncoder::Zstd(encoder)) => {
+                encoder.write_all(chunk)
+                    .map_err(|e| JsValue::from_str(&format!("Zstd compression failed: {}", e)))?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Some(StreamEncoder::Brotli(encoder)) => {
+                encoder.write_all(chunk)
+                    .map_err(|e| JsValue::from_str(&format!("Brotli compression failed: {}", e)))?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            None => Err(JsValue::from_str("StreamingCompressor already finished")),
+        }
+    }
+
+    /// Flush remaining data and the stream trailer
+    pub fn finish(&mut self) -> Result<Vec<u8>, JsValue> {
+        match self.encoder.take() {
+            Some(StreamEncoder::Gzip(encoder)) => encoder.finish()
+                .map_err(|e| JsValue::from_str(&format!("Gzip finalization failed: {}", e))),
+            Some(StreamEncoder::Zstd(encoder)) => encoder.finish()
+                .map_err(|e| JsValue::from_str(&format!("Zstd finalization failed: {}", e))),
+            Some(StreamEncoder::Brotli(encoder)) => Ok(encoder.into_inner()),
+            None => Err(JsValue::from_str("StreamingCompressor already finished")),
+        }
+    }
+}
+
+fn compress_brotli(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
+    let level = brotli_level(level)?;
+    let mut output = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, level, 22);
+        encoder.write_all(data)
+            .map_err(|e| JsValue::from_str(&format!("Brotli compression failed: {}", e)))?;
+    }
+
+    Ok(output)
+}
+
+fn decompress_brotli(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let mut decoder = brotli::Decompressor::new(data, 4096);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)
+        .map_err(|e| JsValue::from_str(&format!("Brotli decompression failed: {}", e)))?;
+
+    Ok(output)
+}
+
+fn decompress_lz4_frame(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let mut decoder = lz4::Decoder::new(data)
+        .map_err(|e| JsValue::from_str(&format!("LZ4 decompression failed: {}", e)))?;
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)
+        .map_err(|e| JsValue::from_str(&format!("LZ4 decompression failed: {}", e)))?;
+
+    Ok(output)
+}
+
+/// Get compression ratio (compressed_size / original_size), so smaller is
+/// better and 1.0 means no reduction
 #[wasm_bindgen]
 pub fn compression_ratio(original_size: usize, compressed_size: usize) -> f64 {
     if original_size == 0 {
@@ -93,5 +577,276 @@ pub fn estimate_compressed_size(original_size: usize, algorithm: Algorithm) -> u
         Algorithm::Gzip => (original_size as f64 * 0.35) as usize, // ~65% reduction
         Algorithm::Zstd => (original_size as f64 * 0.25) as usize, // ~75% reduction
         Algorithm::Lz4 => (original_size as f64 * 0.50) as usize,  // ~50% reduction
+        Algorithm::Brotli => (original_size as f64 * 0.22) as usize, // ~78% reduction
+    }
+}
+
+/// Estimate compressed size by compressing the first `sample_bytes` of
+/// `data` and extrapolating that ratio to the full length. Falls back to
+/// `estimate_compressed_size` when there is nothing to sample.
+#[wasm_bindgen]
+pub fn estimate_compressed_size_sampled(data: &[u8], algorithm: Algorithm, sample_bytes: usize) -> usize {
+    let sample = &data[..sample_bytes.min(data.len())];
+    if sample.is_empty() {
+        return estimate_compressed_size(data.len(), algorithm);
+    }
+
+    let level = match algorithm {
+        Algorithm::Gzip | Algorithm::Brotli => 6,
+        Algorithm::Zstd => 3,
+        Algorithm::Lz4 => 0,
+    };
+    match compress(sample, algorithm, level) {
+        Ok(compressed) => {
+            let ratio = compression_ratio(sample.len(), compressed.len());
+            (data.len() as f64 * ratio).ceil() as usize
+        }
+        Err(_) => estimate_compressed_size(data.len(), algorithm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Incompressible bytes from a fixed-seed xorshift generator
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut seed = 0x9e3779b97f4a7c15u64;
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect()
+    }
+
+    #[test]
+    fn out_of_range_levels_are_rejected() {
+        assert!(gzip_level(9).is_ok());
+        assert_eq!(gzip_level(10).err().as_deref(), Some("gzip level must be 0-9, got 10"));
+        assert!(zstd_level(1).is_ok() && zstd_level(22).is_ok());
+        assert_eq!(zstd_level(0).err().as_deref(), Some("zstd level must be 1-22, got 0"));
+        assert_eq!(zstd_level(23).err().as_deref(), Some("zstd level must be 1-22, got 23"));
+    }
+
+    #[test]
+    fn streaming_round_trips_three_chunks() {
+        let data: Vec<u8> = (0..30_000u32).map(|i| (i % 97) as u8).collect();
+        for algorithm in [Algorithm::Gzip, Algorithm::Zstd] {
+            let mut compressor = StreamingCompressor::new(algorithm, 3).unwrap();
+            let mut compressed = Vec::new();
+            for chunk in data.chunks(10_000) {
+                compressed.extend(compressor.write(chunk).unwrap());
+            }
+            compressed.extend(compressor.finish().unwrap());
+            assert_eq!(decompress(&compressed, algorithm).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn gzip_round_trips_and_ratio_is_compressed_over_original() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(100);
+        let compressed = compress_gzip(&data, 6).unwrap();
+        assert_eq!(decompress_gzip(&compressed).unwrap(), data);
+
+        let ratio = compression_ratio(data.len(), compressed.len());
+        assert!(ratio > 0.0 && ratio < 1.0, "{}", ratio);
+        assert_eq!(compression_ratio(100, 25), 0.25);
+        assert_eq!(compression_ratio(0, 10), 1.0);
+    }
+
+    #[test]
+    fn detect_algorithm_recognizes_framed_formats() {
+        let data = b"detect me ".repeat(50);
+        assert!(matches!(detect_algorithm(&compress(&data, Algorithm::Gzip, 6).unwrap()), Some(Algorithm::Gzip)));
+        assert!(matches!(detect_algorithm(&compress(&data, Algorithm::Zstd, 3).unwrap()), Some(Algorithm::Zstd)));
+        assert!(detect_algorithm(&compress(&data, Algorithm::Lz4, 0).unwrap()).is_none());
+        assert_eq!(decompress_auto(&compress(&data, Algorithm::Zstd, 3).unwrap()).unwrap(), data);
+
+        let mut encoder = lz4::EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(&data).unwrap();
+        let (frame, result) = encoder.finish();
+        result.unwrap();
+        assert!(matches!(detect_algorithm(&frame), Some(Algorithm::Lz4)));
+        assert_eq!(decompress(&frame, Algorithm::Lz4).unwrap(), data);
+    }
+
+    #[test]
+    fn lz4_size_prefix_can_look_like_gzip() {
+        // 35615 is 0x8b1f, so the little-endian size prefix starts 1f 8b
+        let compressed = compress(&[7; 35615], Algorithm::Lz4, 0).unwrap();
+        assert!(matches!(detect_algorithm(&compressed), Some(Algorithm::Gzip)));
+    }
+
+    #[test]
+    fn brotli_round_trips_at_lowest_and_highest_level() {
+        let data = b"brotli brotli brotli, a compressed text sample ".repeat(200);
+        for level in [0, 11] {
+            let compressed = compress(&data, Algorithm::Brotli, level).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(decompress(&compressed, Algorithm::Brotli).unwrap(), data);
+        }
+        assert_eq!(brotli_level(12).err().as_deref(), Some("brotli level must be 0-11, got 12"));
+
+        let mut compressor = StreamingCompressor::new(Algorithm::Brotli, 5).unwrap();
+        let mut streamed = Vec::new();
+        for chunk in data.chunks(1000) {
+            streamed.extend(compressor.write(chunk).unwrap());
+        }
+        streamed.extend(compressor.finish().unwrap());
+        assert_eq!(decompress(&streamed, Algorithm::Brotli).unwrap(), data);
+    }
+
+    #[test]
+    fn lz4_round_trips_a_megabyte() {
+        let data: Vec<u8> = b"0123456789abcdef".iter().copied().cycle().take(1 << 20).collect();
+        let compressed = compress(&data, Algorithm::Lz4, 0).unwrap();
+        assert_eq!(compressed[..4], (data.len() as u32).to_le_bytes());
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(decompress(&compressed, Algorithm::Lz4).unwrap(), data);
+    }
+
+    #[test]
+    fn budget_picks_a_low_level_or_reports_what_cannot_fit() {
+        let compressible = vec![0u8; 100_000];
+        let fitted = fit_to_budget(&compressible, Algorithm::Gzip, 1000).unwrap();
+        assert!(fitted.level <= 1, "level {}", fitted.level);
+        assert!(fitted.data.len() <= 1000);
+        assert_eq!(decompress(&fitted.data, Algorithm::Gzip).unwrap(), compressible);
+
+        let error = fit_to_budget(&random_bytes(10_000), Algorithm::Zstd, 5000).err().unwrap();
+        assert!(error.starts_with("Cannot fit 10000 bytes into 5000 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn batch_compresses_each_item() {
+        let items = vec![b"first item".repeat(10), Vec::new(), vec![42; 5000]];
+        let compressed = compress_all(&items, Algorithm::Zstd, 3).unwrap();
+        assert_eq!(compressed.len(), 3);
+        for (item, compressed) in items.iter().zip(&compressed) {
+            assert_eq!(&decompress(compressed, Algorithm::Zstd).unwrap(), item);
+        }
+    }
+
+    #[test]
+    fn sampled_estimate_tracks_compressibility() {
+        for algorithm in [Algorithm::Gzip, Algorithm::Zstd, Algorithm::Lz4] {
+            let random = estimate_compressed_size_sampled(&random_bytes(100_000), algorithm, 4096);
+            let zeros = estimate_compressed_size_sampled(&[0; 100_000], algorithm, 4096);
+            assert!(random > 90_000, "{}", random);
+            assert!(zeros * 20 < random, "{} vs {}", zeros, random);
+        }
+        assert_eq!(estimate_compressed_size_sampled(&[], Algorithm::Gzip, 4096), 0);
+    }
+
+    #[test]
+    fn dictionary_beats_plain_zstd_on_similar_payloads() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!(r#"{{"pc":{},"regs":[1,2,3,4,5,6,7,8],"mode":"protected","frame":{}}}"#, i * 7, i).into_bytes())
+            .collect();
+        let dict = train_dictionary(&samples, 4096).unwrap();
+
+        let payload = &samples[17];
+        let with_dict = compress_zstd_with_dict(payload, &dict, 3).unwrap();
+        let plain = compress(payload, Algorithm::Zstd, 3).unwrap();
+        assert!(with_dict.len() < plain.len(), "{} vs {}", with_dict.len(), plain.len());
+        assert_eq!(&decompress_zstd_with_dict(&with_dict, &dict).unwrap(), payload);
+    }
+
+    #[test]
+    fn framed_round_trips_every_algorithm() {
+        let data = b"framed payload with its own header ".repeat(40);
+        for algorithm in [Algorithm::Gzip, Algorithm::Zstd, Algorithm::Lz4, Algorithm::Brotli] {
+            let framed = compress_framed(&data, algorithm, 3).unwrap();
+            assert_eq!(framed[..4], FRAME_MAGIC);
+            assert_eq!(framed[4], algorithm as u8);
+            assert_eq!(framed[5..13], (data.len() as u64).to_le_bytes());
+            assert_eq!(decompress_framed(&framed).unwrap(), data);
+        }
+        assert_eq!(decompress_framed(&compress_framed(&[], Algorithm::Zstd, 3).unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn framed_header_is_checked() {
+        let data = b"length check ".repeat(20);
+        let mut framed = compress_framed(&data, Algorithm::Gzip, 6).unwrap();
+        assert!(read_frame_header(&framed[1..]).is_err());
+
+        framed[4] = 9;
+        assert_eq!(read_frame_header(&framed).err().as_deref(), Some("Unknown algorithm byte 9 in frame header"));
+
+        framed[4] = Algorithm::Gzip as u8;
+        framed[5] ^= 1;
+        let (algorithm, original_len, body) = read_frame_header(&framed).unwrap();
+        let output = decompress(body, algorithm).unwrap();
+        assert_eq!(
+            check_frame_len(&output, original_len).err().as_deref(),
+            Some("Framed data decompressed to 260 bytes, header says 261")
+        );
+    }
+
+    #[test]
+    fn verified_compression_catches_a_bad_round_trip() {
+        let data = b"verify me after compressing ".repeat(30);
+        for algorithm in [Algorithm::Gzip, Algorithm::Zstd, Algorithm::Lz4, Algorithm::Brotli] {
+            let verified = compress_verified(&data, algorithm, 3).unwrap();
+            assert_eq!(verified, compress(&data, algorithm, 3).unwrap());
+        }
+
+        // A decompressor that loses the last byte, and one that flips a byte
+        let compressed = compress(&data, Algorithm::Zstd, 3).unwrap();
+        let truncated = verify_round_trip(&data, &compressed, |c| {
+            let mut out = decompress(c, Algorithm::Zstd).map_err(|e| e.as_string().unwrap_or_default())?;
+            out.pop();
+            Ok(out)
+        });
+        assert_eq!(truncated.err().unwrap(), format!("Verification failed: decompressed to {} bytes, expected {}", data.len() - 1, data.len()));
+
+        let corrupted = verify_round_trip(&data, &compressed, |c| {
+            let mut out = decompress(c, Algorithm::Zstd).map_err(|e| e.as_string().unwrap_or_default())?;
+            out[100] ^= 0xff;
+            Ok(out)
+        });
+        assert_eq!(corrupted.err().as_deref(), Some("Verification failed: output differs at byte 100"));
+        assert!(verify_round_trip(&data, &compressed, |_| Err("broken".to_string())).is_err());
+    }
+
+    #[test]
+    fn multithreaded_and_single_threaded_zstd_both_round_trip() {
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect();
+        let multi = compress_zstd_mt(&data, 3, 4).unwrap();
+        let single = compress_zstd_mt(&data, 3, 1).unwrap();
+        assert!(detect_algorithm(&multi).is_some());
+        assert_eq!(decompress(&multi, Algorithm::Zstd).unwrap(), data);
+        assert_eq!(decompress(&single, Algorithm::Zstd).unwrap(), data);
+    }
+
+    #[test]
+    fn decompress_into_fills_a_sized_buffer_and_rejects_a_short_one() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        for algorithm in [Algorithm::Gzip, Algorithm::Zstd, Algorithm::Lz4, Algorithm::Brotli] {
+            let compressed = compress(&data, algorithm, 3).unwrap();
+
+            let mut out = vec![0; data.len()];
+            assert_eq!(decompress_to_slice(&compressed, algorithm, &mut out), Ok(data.len()));
+            assert_eq!(out, data);
+
+            let mut short = vec![0; data.len() - 1];
+            assert!(decompress_to_slice(&compressed, algorithm, &mut short).is_err());
+        }
+    }
+
+    #[test]
+    fn compress_best_keeps_the_smallest_output() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(200)
+            + &(0..500).map(|i| format!("line {} of the log\n", i)).collect::<String>();
+        let best = compress_best(text.as_bytes(), 19).unwrap();
+
+        let (algorithm, _, _) = read_frame_header(&best.data).unwrap();
+        assert_eq!(algorithm as u8, best.algorithm as u8);
+        for (algorithm, level) in [(Algorithm::Gzip, 9), (Algorithm::Zstd, 19), (Algorithm::Lz4, 0)] {
+            assert!(best.data.len() <= compress_framed(text.as_bytes(), algorithm, level).unwrap().len());
+        }
+        assert_eq!(decompress_framed(&best.data).unwrap(), text.as_bytes());
     }
 }
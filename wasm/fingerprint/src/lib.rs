@@ -1,5 +1,8 @@
 use wasm_bindgen::prelude::*;
 use sha2::{Sha256, Digest};
+use hmac::{Hmac, Mac};
+
+type HmacSha256 = Hmac<Sha256>;
 
 /// Fast SHA-256 hashing for fingerprint generation
 #[wasm_bindgen]
@@ -9,16 +12,67 @@ pub fn hash_sha256(data: &[u8]) -> Vec<u8> {
     hasher.finalize().to_vec()
 }
 
-/// Hash multiple data sources and combine them
+/// HMAC-SHA256 of data under `key`. Keys of any length are accepted
+#[wasm_bindgen]
+pub fn hash_sha256_keyed(data: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut mac = keyed_hasher(key);
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn keyed_hasher(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+/// Hash function selector for `hash_with`
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum HashAlgorithm {
+    Sha256 = 0,
+    Blake3 = 1,
+}
+
+/// Fast BLAKE3 hashing (32-byte digest), much quicker than SHA-256 in wasm
+#[wasm_bindgen]
+pub fn hash_blake3(data: &[u8]) -> Vec<u8> {
+    blake3::hash(data).as_bytes().to_vec()
+}
+
+/// BLAKE3 hash as a hex string
+#[wasm_bindgen]
+pub fn hash_blake3_hex(data: &[u8]) -> String {
+    bytes_to_hex(&hash_blake3(data))
+}
+
+/// Hash data with the selected algorithm
+#[wasm_bindgen]
+pub fn hash_with(data: &[u8], algorithm: HashAlgorithm) -> Vec<u8> {
+    match algorithm {
+        HashAlgorithm::Sha256 => hash_sha256(data),
+        HashAlgorithm::Blake3 => hash_blake3(data),
+    }
+}
+
+/// Hash multiple data sources and combine them.
+/// Each source must be a string, `Uint8Array` or `ArrayBuffer`; anything
+/// else is rejected so a bad caller can't produce a plausible-looking hash.
 #[wasm_bindgen]
 pub fn hash_combined(data_sources: Vec<JsValue>) -> Result<Vec<u8>, JsValue> {
     let mut hasher = Sha256::new();
-    
-    for value in data_sources {
-        if let Some(bytes) = value.as_string() {
-            hasher.update(bytes.as_bytes());
-        } else if let Ok(bytes) = js_sys::Uint8Array::try_from(value) {
-            hasher.update(&bytes.to_vec());
+
+    for (index, value) in data_sources.into_iter().enumerate() {
+        if let Some(text) = value.as_string() {
+            hasher.update(text.as_bytes());
+        } else if let Some(bytes) = value.dyn_ref::<js_sys::Uint8Array>() {
+            hasher.update(bytes.to_vec());
+        } else if let Some(buffer) = value.dyn_ref::<js_sys::ArrayBuffer>() {
+            hasher.update(js_sys::Uint8Array::new(buffer).to_vec());
+        } else {
+            let kind = value.js_typeof().as_string().unwrap_or_default();
+            return Err(JsValue::from_str(&format!(
+                "Unsupported data source at index {}: {}",
+                index, kind
+            )));
         }
     }
     
@@ -33,47 +87,420 @@ pub fn bytes_to_hex(bytes: &[u8]) -> String {
         .collect()
 }
 
-/// Canvas fingerprinting helper - hash pixel data
+/// Compare two hashes in constant time (for equal-length inputs).
+/// Inputs of different length are never equal.
+#[wasm_bindgen]
+pub fn hashes_equal(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    // Accumulate every difference instead of returning at the first one
+    let diff = a.iter()
+        .zip(b)
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    diff == 0
+}
+
+/// Constant-time comparison of two hex-encoded hashes (case-insensitive)
+#[wasm_bindgen]
+pub fn hex_equal(a: &str, b: &str) -> bool {
+    hashes_equal(
+        a.to_ascii_lowercase().as_bytes(),
+        b.to_ascii_lowercase().as_bytes(),
+    )
+}
+
+/// Every `sample_rate`-th RGBA pixel of a canvas buffer, after checking the
+/// buffer is exactly `width * height * 4` bytes
+fn sampled_pixels(pixels: &[u8], width: u32, height: u32, sample_rate: usize) -> Result<impl Iterator<Item = &[u8]>, String> {
+    if sample_rate == 0 {
+        return Err("sample_rate must be greater than 0".to_string());
+    }
+    let expected_len = width as u64 * height as u64 * 4;
+    if pixels.len() as u64 != expected_len {
+        return Err(format!(
+            "Pixel buffer is {} bytes, expected {} for {}x{} RGBA",
+            pixels.len(), expected_len, width, height
+        ));
+    }
+
+    Ok(pixels.chunks_exact(4).step_by(sample_rate))
+}
+
+/// Canvas fingerprinting helper - hash pixel data, sampling every
+/// `sample_rate`-th pixel for speed
 #[wasm_bindgen]
-pub fn hash_canvas_data(pixels: &[u8], width: u32, height: u32) -> String {
+pub fn hash_canvas_data(pixels: &[u8], width: u32, height: u32, sample_rate: usize) -> Result<String, JsValue> {
     let mut hasher = Sha256::new();
-    
-    // Sample pixels at intervals for faster hashing
-    let sample_rate = 16;
-    let total_pixels = (width * height) as usize;
-    
-    for i in (0..total_pixels).step_by(sample_rate) {
-        let offset = i * 4; // RGBA
-        if offset + 3 < pixels.len() {
-            hasher.update(&pixels[offset..offset + 4]);
+
+    for pixel in sampled_pixels(pixels, width, height, sample_rate)? {
+        hasher.update(pixel);
+    }
+
+    Ok(bytes_to_hex(&hasher.finalize()))
+}
+
+/// Similarity of two canvas renders from 0.0 (every sampled channel is at
+/// opposite extremes) to 1.0 (identical samples): one minus the mean absolute
+/// per-channel difference over the pixels `hash_canvas_data` would sample.
+/// Small anti-aliasing differences score just under 1.0.
+#[wasm_bindgen]
+pub fn canvas_similarity(a: &[u8], b: &[u8], width: u32, height: u32, sample_rate: usize) -> Result<f64, JsValue> {
+    let pixels_a = sampled_pixels(a, width, height, sample_rate)?;
+    let pixels_b = sampled_pixels(b, width, height, sample_rate)?;
+
+    let mut total_diff = 0u64;
+    let mut channels = 0u64;
+    for (pa, pb) in pixels_a.zip(pixels_b) {
+        for (x, y) in pa.iter().zip(pb) {
+            total_diff += x.abs_diff(*y) as u64;
+            channels += 1;
         }
     }
-    
-    bytes_to_hex(&hasher.finalize())
+
+    if channels == 0 {
+        return Ok(1.0);
+    }
+    Ok(1.0 - total_diff as f64 / (channels as f64 * 255.0))
 }
 
-/// Audio fingerprinting helper - hash audio data
+/// Give each sample value a single bit pattern: every NaN becomes the
+/// canonical quiet NaN and -0.0 becomes +0.0. Other values, subnormals
+/// included, are hashed as they are
+fn canonical_sample(sample: f32) -> f32 {
+    if sample.is_nan() {
+        f32::NAN
+    } else if sample == 0.0 {
+        0.0
+    } else {
+        sample
+    }
+}
+
+/// Audio fingerprinting helper - hash every `step`-th audio sample
 #[wasm_bindgen]
-pub fn hash_audio_data(samples: &[f32]) -> String {
+pub fn hash_audio_data(samples: &[f32], step: usize) -> Result<String, JsValue> {
+    if step == 0 {
+        return Err(JsValue::from_str("step must be greater than 0"));
+    }
+
     let mut hasher = Sha256::new();
     
     // Convert float samples to bytes and hash
-    for sample in samples.iter().step_by(100) {
-        hasher.update(&sample.to_le_bytes());
+    for &sample in samples.iter().step_by(step) {
+        hasher.update(canonical_sample(sample).to_le_bytes());
+    }
+
+    Ok(bytes_to_hex(&hasher.finalize()))
+}
+
+/// Validate `tolerance` and round each metric to its bucket index
+fn metric_buckets(metrics: &[f64], tolerance: f64) -> Result<impl Iterator<Item = i64> + '_, String> {
+    if !(tolerance > 0.0 && tolerance.is_finite()) {
+        return Err("tolerance must be a positive number".to_string());
+    }
+
+    Ok(metrics.iter().map(move |metric| (metric / tolerance).round() as i64))
+}
+
+/// Font fingerprinting helper - hash measured text widths after rounding
+/// each to the nearest multiple of `tolerance`, so sub-pixel rendering noise
+/// doesn't change the hash. Values that straddle a bucket edge can still
+/// round apart, however close they are.
+#[wasm_bindgen]
+pub fn hash_font_metrics(metrics: Vec<f64>, tolerance: f64) -> Result<String, JsValue> {
+    let mut hasher = Sha256::new();
+
+    for bucket in metric_buckets(&metrics, tolerance)? {
+        hasher.update(bucket.to_le_bytes());
+    }
+
+    Ok(bytes_to_hex(&hasher.finalize()))
+}
+
+/// Incremental fingerprint hashing. Each update is one component followed
+/// by the `|` separator, so feeding the same components produces the same
+/// id as `generate_fingerprint_id`.
+#[wasm_bindgen]
+pub struct FingerprintHasher {
+    hasher: Sha256,
+}
+
+impl Default for FingerprintHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl FingerprintHasher {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { hasher: Sha256::new() }
+    }
+
+    /// Add a string component
+    pub fn update_str(&mut self, s: &str) {
+        self.update_bytes(s.as_bytes());
+    }
+
+    /// Add a binary component
+    pub fn update_bytes(&mut self, b: &[u8]) {
+        self.hasher.update(b);
+        self.hasher.update(b"|"); // Separator
+    }
+
+    /// Finish hashing and return the hex id
+    pub fn finalize_hex(self) -> String {
+        bytes_to_hex(&self.hasher.finalize())
     }
-    
-    bytes_to_hex(&hasher.finalize())
 }
 
 /// Generate a unique ID from multiple fingerprint components
 #[wasm_bindgen]
 pub fn generate_fingerprint_id(components: Vec<String>) -> String {
-    let mut hasher = Sha256::new();
-    
+    let mut hasher = FingerprintHasher::new();
+
     for component in components {
-        hasher.update(component.as_bytes());
-        hasher.update(b"|"); // Separator
+        hasher.update_str(&component);
+    }
+
+    hasher.finalize_hex()
+}
+
+/// Keyed variant of `generate_fingerprint_id`: the same components under
+/// HMAC-SHA256 with a per-origin key, so ids derived with different keys
+/// can't be correlated without knowing the keys
+#[wasm_bindgen]
+pub fn generate_fingerprint_id_keyed(components: Vec<String>, key: &[u8]) -> String {
+    let mut mac = keyed_hasher(key);
+
+    for component in components {
+        mac.update(component.as_bytes());
+        mac.update(b"|"); // Separator
+    }
+
+    bytes_to_hex(&mac.finalize().into_bytes())
+}
+
+/// Order-independent variant of `generate_fingerprint_id`: components are
+/// sorted before hashing, so enumeration order doesn't change the id
+#[wasm_bindgen]
+pub fn generate_fingerprint_id_stable(mut components: Vec<String>) -> String {
+    components.sort();
+    generate_fingerprint_id(components)
+}
+
+/// Rough bits-of-entropy estimate for a set of fingerprint components:
+/// the Shannon entropy of the byte distribution of their concatenation,
+/// multiplied by its length. A heuristic for flagging low-entropy
+/// fingerprints (e.g. all empty strings), not a uniqueness guarantee.
+#[wasm_bindgen]
+pub fn entropy_bits(components: Vec<String>) -> f64 {
+    let mut counts = [0usize; 256];
+    let mut total = 0usize;
+
+    for component in &components {
+        for &byte in component.as_bytes() {
+            counts[byte as usize] += 1;
+            total += 1;
+        }
+    }
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    let bits_per_byte: f64 = counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum();
+
+    bits_per_byte * total as f64
+}
+
+/// Output of `hash_webgl_params`
+#[wasm_bindgen(getter_with_clone)]
+pub struct WebglFingerprint {
+    pub hash: String,
+    /// `entropy_bits` of the parameters. Headless browsers tend to return
+    /// empty parameter strings, which shows up as (near) zero.
+    pub entropy: f64,
+}
+
+/// Fingerprint a set of WebGL parameter strings. They're sorted first, so
+/// collection order doesn't matter, and hashed with the `|` separator like
+/// `generate_fingerprint_id`.
+#[wasm_bindgen]
+pub fn hash_webgl_params(params: Vec<String>) -> WebglFingerprint {
+    let entropy = entropy_bits(params.clone());
+    WebglFingerprint {
+        hash: generate_fingerprint_id_stable(params),
+        entropy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_buffer_must_match_its_dimensions() {
+        let pixels: Vec<u8> = (0..2 * 2 * 4).map(|i| i as u8).collect();
+        assert_eq!(hash_canvas_data(&pixels, 2, 2, 1).unwrap(), bytes_to_hex(&hash_sha256(&pixels)));
+        let every_other: Vec<u8> = [&pixels[0..4], &pixels[8..12]].concat();
+        assert_eq!(hash_canvas_data(&pixels, 2, 2, 2).unwrap(), bytes_to_hex(&hash_sha256(&every_other)));
+
+        assert_eq!(
+            sampled_pixels(&pixels[..12], 2, 2, 1).err().as_deref(),
+            Some("Pixel buffer is 12 bytes, expected 16 for 2x2 RGBA")
+        );
+        assert_eq!(sampled_pixels(&pixels, 2, 2, 0).err().as_deref(), Some("sample_rate must be greater than 0"));
+    }
+
+    #[test]
+    fn canvas_similarity_separates_noise_from_changes() {
+        let render: Vec<u8> = (0..16 * 16 * 4).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(canvas_similarity(&render, &render, 16, 16, 1).unwrap(), 1.0);
+
+        // Anti-aliasing style noise: a few channels off by one or two
+        let mut noisy = render.clone();
+        for i in (0..noisy.len()).step_by(37) {
+            noisy[i] = noisy[i].wrapping_add(2);
+        }
+        assert!(canvas_similarity(&render, &noisy, 16, 16, 1).unwrap() > 0.99);
+
+        let black = vec![0u8; 16 * 16 * 4];
+        let white = vec![255u8; 16 * 16 * 4];
+        assert_eq!(canvas_similarity(&black, &white, 16, 16, 3).unwrap(), 0.0);
+        assert_eq!(canvas_similarity(&[], &[], 0, 0, 1).unwrap(), 1.0);
+    }
+
+    // Building a JsValue needs a JS host, so this only runs under
+    // `wasm-bindgen-test-runner`
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn hash_combined_rejects_numbers() {
+        let sources = vec![JsValue::from_str("text"), JsValue::from_f64(42.0)];
+        let error = hash_combined(sources).unwrap_err().as_string().unwrap();
+        assert_eq!(error, "Unsupported data source at index 1: number");
+        assert!(hash_combined(vec![JsValue::from_str("text")]).is_ok());
+    }
+
+    #[test]
+    fn hashes_equal_compares_contents_and_length() {
+        let a = hash_sha256(b"a");
+        assert!(hashes_equal(&a, &hash_sha256(b"a")));
+        assert!(!hashes_equal(&a, &hash_sha256(b"b")));
+        assert!(!hashes_equal(&a, &a[..31]));
+        assert!(!hashes_equal(b"", b"\0"));
+        assert!(hashes_equal(b"", b""));
+        assert!(hex_equal("ABcd01", "abCD01"));
+        assert!(!hex_equal("abcd", "abcd00"));
+    }
+
+    #[test]
+    fn stable_id_ignores_component_order() {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let id = generate_fingerprint_id_stable(strings(&["ua", "tz", "gpu"]));
+        assert_eq!(id, generate_fingerprint_id_stable(strings(&["gpu", "ua", "tz"])));
+        assert_ne!(id, generate_fingerprint_id_stable(strings(&["gpu", "ua", "tz2"])));
+        assert_ne!(generate_fingerprint_id(strings(&["ua", "tz"])), generate_fingerprint_id(strings(&["tz", "ua"])));
+    }
+
+    #[test]
+    fn streaming_hasher_matches_fingerprint_id() {
+        let mut hasher = FingerprintHasher::new();
+        hasher.update_str("ua");
+        hasher.update_bytes(b"tz");
+        hasher.update_str("");
+        let components = vec!["ua".to_string(), "tz".to_string(), String::new()];
+        assert_eq!(hasher.finalize_hex(), generate_fingerprint_id(components));
+    }
+
+    #[test]
+    fn audio_hash_canonicalizes_zero_and_nan() {
+        let positive = hash_audio_data(&[0.5, 0.0, 0.25, f32::NAN], 1).unwrap();
+        let negative = hash_audio_data(&[0.5, -0.0, 0.25, -f32::NAN], 1).unwrap();
+        assert_eq!(positive, negative);
+        assert_ne!(hash_audio_data(&[1e-40, 1.0], 1).unwrap(), hash_audio_data(&[0.0, 1.0], 1).unwrap());
+        assert_ne!(positive, hash_audio_data(&[0.5, 0.1, 0.25, f32::NAN], 1).unwrap());
+        // A step of 2 only hashes samples 0 and 2
+        assert_eq!(hash_audio_data(&[0.5, 9.0, 0.25], 2).unwrap(), hash_audio_data(&[0.5, 0.25], 1).unwrap());
+    }
+
+    #[test]
+    fn blake3_matches_known_vectors() {
+        assert_eq!(hash_blake3_hex(b""), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(hash_blake3_hex(b"abc"), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!(hash_with(b"abc", HashAlgorithm::Blake3), hash_blake3(b"abc"));
+        assert_eq!(hash_with(b"abc", HashAlgorithm::Sha256), hash_sha256(b"abc"));
+    }
+
+    #[test]
+    fn varied_components_have_more_entropy() {
+        let varied: Vec<String> = ["Mozilla/5.0 (X11; Linux x86_64)", "Europe/Berlin", "ANGLE (NVIDIA)", "1920x1080"]
+            .iter().map(|s| s.to_string()).collect();
+        let repeated = vec!["aaaaaaaa".to_string(); 4];
+        assert!(entropy_bits(varied) > entropy_bits(repeated.clone()));
+        assert_eq!(entropy_bits(repeated), 0.0);
+        assert_eq!(entropy_bits(vec![String::new(); 3]), 0.0);
+    }
+
+    #[test]
+    fn webgl_params_hash_ignores_order_and_flags_empty_params() {
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let params = ["WebKit WebGL", "ANGLE (Intel, Mesa Intel(R) UHD Graphics 620)", "MAX_TEXTURE_SIZE=16384"];
+        let forward = hash_webgl_params(strings(&params));
+        let reversed = hash_webgl_params(strings(&[params[2], params[0], params[1]]));
+        assert_eq!(forward.hash, reversed.hash);
+        assert_eq!(forward.hash, generate_fingerprint_id_stable(strings(&params)));
+        assert!(forward.entropy > 100.0, "{}", forward.entropy);
+
+        let headless = hash_webgl_params(strings(&["", "", ""]));
+        assert_eq!(headless.entropy, 0.0);
+        assert_ne!(headless.hash, forward.hash);
+    }
+
+    #[test]
+    fn keyed_hash_matches_rfc_4231_vectors() {
+        assert_eq!(
+            bytes_to_hex(&hash_sha256_keyed(b"Hi There", &[0x0b; 20])),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            bytes_to_hex(&hash_sha256_keyed(b"what do ya want for nothing?", b"Jefe")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn keyed_ids_differ_across_keys() {
+        let components = || vec!["Mozilla/5.0".to_string(), "1920x1080".to_string()];
+        let a = generate_fingerprint_id_keyed(components(), b"origin-a.example");
+        let b = generate_fingerprint_id_keyed(components(), b"origin-b.example");
+        assert_ne!(a, b);
+        assert_eq!(a, generate_fingerprint_id_keyed(components(), b"origin-a.example"));
+        assert_ne!(a, generate_fingerprint_id(components()));
+    }
+
+    #[test]
+    fn font_metrics_within_tolerance_hash_alike() {
+        let metrics = vec![412.0, 388.5, 401.0];
+        let jittered = vec![412.12, 388.41, 401.1];
+        let shifted = vec![412.0, 391.5, 401.0];
+
+        let hash = hash_font_metrics(metrics.clone(), 0.5).unwrap();
+        assert_eq!(hash_font_metrics(jittered, 0.5).unwrap(), hash);
+        assert_ne!(hash_font_metrics(shifted, 0.5).unwrap(), hash);
+        // Order still matters: each metric is one font's width
+        assert_ne!(hash_font_metrics(vec![388.5, 412.0, 401.0], 0.5).unwrap(), hash);
+        assert!(metric_buckets(&metrics, 0.0).is_err());
+        assert!(metric_buckets(&metrics, f64::NAN).is_err());
+        assert!(metric_buckets(&metrics, f64::INFINITY).is_err());
     }
-    
-    bytes_to_hex(&hasher.finalize())
 }
@@ -1,9 +1,13 @@
 use wasm_bindgen::prelude::*;
-use quick_xml::events::Event;
-use quick_xml::Reader;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::name::QName;
+use quick_xml::{Reader, Writer};
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
 #[wasm_bindgen(getter_with_clone)]
 pub struct GameInfo {
     pub id: String,
@@ -14,110 +18,802 @@ pub struct GameInfo {
     pub category: String,
     pub width: u32,
     pub height: u32,
+    /// Enclosing section tag the game was found under, or `"default"`
+    pub section: String,
+}
+
+impl GameInfo {
+    fn empty() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            description: String::new(),
+            thumbnail: String::new(),
+            url: String::new(),
+            category: String::new(),
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            section: DEFAULT_SECTION.to_string(),
+        }
+    }
+
+    /// Set one of the text fields by its canonical name; unknown names are ignored
+    fn set_text_field(&mut self, field: &str, value: String) {
+        match field {
+            "name" => self.name = value,
+            "description" => self.description = value,
+            "thumbnail" => self.thumbnail = value,
+            "url" => self.url = value,
+            "category" => self.category = value,
+            _ => {}
+        }
+    }
+
+    /// Read one of the text fields by its canonical name
+    fn text_field(&self, field: &str) -> Option<&str> {
+        match field {
+            "name" => Some(&self.name),
+            "description" => Some(&self.description),
+            "thumbnail" => Some(&self.thumbnail),
+            "url" => Some(&self.url),
+            "category" => Some(&self.category),
+            _ => None,
+        }
+    }
+
+    /// `validate` with the error as a plain message
+    fn check(&self) -> Result<(), String> {
+        for (field, value) in [("id", &self.id), ("name", &self.name), ("url", &self.url)] {
+            if value.trim().is_empty() {
+                return Err(format!("Game \"{}\" has an empty {}", self.id, field));
+            }
+        }
+        if !is_http_url(&self.url) {
+            return Err(format!("Game \"{}\" has a non-http(s) url: {}", self.id, self.url));
+        }
+        if !self.thumbnail.is_empty() && !is_http_url(&self.thumbnail) {
+            return Err(format!("Game \"{}\" has a non-http(s) thumbnail: {}", self.id, self.thumbnail));
+        }
+        Ok(())
+    }
+
+    /// Trim `url` and `thumbnail` and resolve relative ones against `base`
+    fn normalize_urls(&mut self, base: &str) {
+        self.url = resolve_url(base, self.url.trim());
+        self.thumbnail = resolve_url(base, self.thumbnail.trim());
+    }
+}
+
+#[wasm_bindgen]
+impl GameInfo {
+    /// Check that id, name and url are present and that url and thumbnail
+    /// (when set) are http(s). Feed text is untrusted, so run this before
+    /// using either as an iframe or image source.
+    pub fn validate(&self) -> Result<(), JsValue> {
+        self.check().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Width divided by height, or 0 when the height is 0
+    pub fn aspect_ratio(&self) -> f64 {
+        if self.height == 0 {
+            return 0.0;
+        }
+        self.width as f64 / self.height as f64
+    }
+}
+
+/// Whether `url` is an absolute http or https URL with something after the scheme
+fn is_http_url(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    ["http://", "https://"].iter()
+        .any(|scheme| lower.len() > scheme.len() && lower.starts_with(scheme))
+}
+
+/// Whether `name` can be written as an XML element name: a letter or `_`
+/// followed by letters, digits, `-`, `.`, `_` or `:`
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '.' | '_' | ':'))
+}
+
+/// Whether lowercased `query` occurs in `text`, either anywhere or (with
+/// `whole_word`) only where it isn't part of a longer word
+fn text_matches(text: &str, query: &str, whole_word: bool) -> bool {
+    let text = text.to_lowercase();
+    if !whole_word {
+        return text.contains(query);
+    }
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    text.match_indices(query).any(|(start, _)| {
+        !is_word(text[..start].chars().next_back()) && !is_word(text[start + query.len()..].chars().next())
+    })
+}
+
+/// Edit distance between `a` and `b`, counting characters
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != cb);
+            row[j + 1] = substitute.min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[b.len()]
+}
+
+/// SHA-256 of a feed as lowercase hex, usable as an ETag for the content
+#[wasm_bindgen]
+pub fn feed_hash(xml_data: &str) -> String {
+    Sha256::digest(xml_data.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 1-based line and column of byte `offset` in `text`
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text.as_bytes()[..offset.min(text.len())];
+    let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    (line, before.len() - line_start + 1)
+}
+
+/// Scheme of `url` (`javascript`, `https`, ...) if it has one
+fn url_scheme(url: &str) -> Option<&str> {
+    let (scheme, _) = url.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+/// Resolve `url` against `base` the way a browser would for the common
+/// cases: absolute and empty URLs are kept, `//host` takes the base scheme,
+/// `/path` the base origin, and anything else the base directory, with
+/// `.` and `..` segments collapsed
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.is_empty() || url_scheme(url).is_some() {
+        return url.to_string();
+    }
+    let Some(scheme) = url_scheme(base) else {
+        return url.to_string();
+    };
+    if url.starts_with("//") {
+        return format!("{}:{}", scheme, url);
+    }
+
+    // Split the base into origin and path
+    let after_scheme = &base[scheme.len() + 1..];
+    let authority_len = after_scheme.strip_prefix("//")
+        .map(|rest| 2 + rest.find(['/', '?', '#']).unwrap_or(rest.len()))
+        .unwrap_or(0);
+    let origin = &base[..scheme.len() + 1 + authority_len];
+    let base_path = &after_scheme[authority_len..];
+    let base_path = &base_path[..base_path.find(['?', '#']).unwrap_or(base_path.len())];
+
+    let joined = if url.starts_with('/') {
+        url.to_string()
+    } else {
+        let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+        let dir = if dir.is_empty() { "/" } else { dir };
+        format!("{}{}", dir, url)
+    };
+
+    // Collapse dot segments in the path, leaving any query or fragment alone
+    let (path, suffix) = joined.split_at(joined.find(['?', '#']).unwrap_or(joined.len()));
+    let mut segments: Vec<&str> = Vec::new();
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    for (i, segment) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        match *segment {
+            "." | ".." => {
+                if *segment == ".." {
+                    segments.pop();
+                }
+                if last {
+                    segments.push("");
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+    format!("{}/{}{}", origin, segments.join("/"), suffix)
+}
+
+impl Default for GameInfo {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// A game in a JSON catalog. Every field is optional; width and height are
+/// pulled out so missing ones get the parser's default size rather than
+/// `GameInfo`'s.
+#[derive(Deserialize)]
+struct JsonGame {
+    #[serde(flatten)]
+    info: GameInfo,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// One page of games plus the totals a pagination UI needs
+#[derive(Serialize)]
+struct GamePage<'a> {
+    items: &'a [GameInfo],
+    total: usize,
+    page: usize,
+    total_pages: usize,
+}
+
+/// Section assigned to games that aren't inside a registered section tag
+const DEFAULT_SECTION: &str = "default";
+
+/// Size used for games that don't specify one, unless the parser overrides it
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+
+/// Child elements whose text populates a `GameInfo` field
+const TEXT_FIELDS: [&str; 5] = ["name", "description", "thumbnail", "url", "category"];
+
+/// Reader position within the catalog, kept across events (and across
+/// chunks when the feed arrives in pieces)
+#[derive(Default)]
+struct ReadState {
+    /// Open elements above the current event, so `<game>` can be nested
+    /// at any depth and still learn which section it belongs to
+    open_tags: Vec<String>,
+    in_game: bool,
+    game_offset: usize,
+    current_game: GameInfo,
+    current_field: String,
+}
+
+/// Progress of a feed read in chunks with `feed` and `finish`
+#[derive(Default)]
+struct FeedState {
+    /// Received text not yet parsed: the start of an incomplete tag or text
+    pending: String,
+    state: ReadState,
+    /// Bytes of the feed before `pending`, for error positions
+    consumed: usize,
 }
 
 #[wasm_bindgen]
 pub struct GameParser {
     games: Vec<GameInfo>,
+    section_tags: Vec<String>,
+    strict: bool,
+    skipped: usize,
+    /// Lowercased alias element name -> canonical field name
+    field_aliases: HashMap<String, String>,
+    /// When set, game URLs are trimmed and resolved against this base
+    base_url: Option<String>,
+    /// Compare element and attribute names without their namespace prefix
+    namespace_aware: bool,
+    /// Size given to games missing (or with unparseable) dimensions
+    default_width: u32,
+    default_height: u32,
+    /// `feed_hash` of the last XML feed parsed without error
+    last_feed_hash: Option<String>,
+    /// Chunked feed in progress
+    feed: FeedState,
+}
+
+impl Default for GameParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[wasm_bindgen]
 impl GameParser {
-    #[wasm_bindgen(constructor)]
-    pub fn new() -> Self {
-        Self { games: Vec::new() }
+    /// Innermost open element that is a registered section tag
+    fn section_for(&self, open_tags: &[String]) -> String {
+        open_tags.iter()
+            .rev()
+            .find(|tag| self.section_tags.contains(tag))
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_SECTION.to_string())
     }
-    
-    /// Parse XML game data (streaming, zero-copy where possible)
-    pub fn parse_xml(&mut self, xml_data: &str) -> Result<(), JsValue> {
+
+    /// Canonical field an element maps to, following registered aliases
+    fn field_name<'a>(&'a self, element: &'a str) -> &'a str {
+        self.field_aliases.get(&element.to_lowercase())
+            .map(String::as_str)
+            .unwrap_or(element)
+    }
+
+    /// Element or attribute name as it is matched against `game`, field
+    /// names and section tags: `g:game` becomes `game` in namespace-aware mode
+    fn element_name(&self, name: QName) -> String {
+        let name = if self.namespace_aware { name.local_name().into_inner() } else { name.into_inner() };
+        String::from_utf8_lossy(name).into_owned()
+    }
+
+    /// Start a game from its `<game>` tag, reading any fields carried as
+    /// attributes. Child elements parsed later override these.
+    fn begin_game(&self, e: &BytesStart, open_tags: &[String]) -> GameInfo {
+        let mut game = GameInfo::empty();
+        game.section = self.section_for(open_tags);
+        game.width = self.default_width;
+        game.height = self.default_height;
+
+        for attr in e.attributes().flatten() {
+            let key = self.element_name(attr.key);
+            let value = attr.unescape_value().unwrap_or_default().to_string();
+
+            match key.as_str() {
+                "id" => game.id = value,
+                "width" => game.width = value.parse().unwrap_or(self.default_width),
+                "height" => game.height = value.parse().unwrap_or(self.default_height),
+                _ => game.set_text_field(self.field_name(&key), value),
+            }
+        }
+        game
+    }
+
+    /// Apply the id check to a completed game. Returns `None` when the game
+    /// was skipped.
+    fn finish_game(&mut self, mut game: GameInfo, location: std::fmt::Arguments) -> Result<Option<GameInfo>, String> {
+        if let Some(base) = &self.base_url {
+            game.normalize_urls(base);
+        }
+        if !game.id.is_empty() {
+            return Ok(Some(game));
+        }
+        if self.strict {
+            return Err(format!("Game \"{}\" at {} has no id", game.name, location));
+        }
+        self.skipped += 1;
+        Ok(None)
+    }
+
+    /// Drive the XML reader, handing each completed game to `on_game`.
+    /// Parsing stops early when `on_game` returns `Ok(false)`.
+    fn read_games<F, E>(&mut self, xml_data: &str, mut on_game: F) -> Result<(), E>
+    where
+        F: FnMut(&mut Self, GameInfo) -> Result<bool, E>,
+        E: From<String>,
+    {
         let mut reader = Reader::from_str(xml_data);
         reader.trim_text(true);
         
         let mut buf = Vec::new();
-        let mut current_game = GameInfo {
-            id: String::new(),
-            name: String::new(),
-            description: String::new(),
-            thumbnail: String::new(),
-            url: String::new(),
-            category: String::new(),
-            width: 800,
-            height: 600,
-        };
-        
-        let mut in_game = false;
-        let mut current_field = String::new();
+        let mut state = ReadState::default();
         
         loop {
+            let event_offset = reader.buffer_position();
             match reader.read_event_into(&mut buf) {
-                Ok(Event::Start(ref e)) => {
-                    let name = e.name();
-                    let name_str = std::str::from_utf8(name.as_ref())
-                        .unwrap_or("");
-                    
-                    if name_str == "game" {
-                        in_game = true;
-                        current_game = GameInfo {
-                            id: String::new(),
-                            name: String::new(),
-                            description: String::new(),
-                            thumbnail: String::new(),
-                            url: String::new(),
-                            category: String::new(),
-                            width: 800,
-                            height: 600,
-                        };
-                        
-                        // Parse attributes
-                        for attr in e.attributes() {
-                            if let Ok(attr) = attr {
-                                let key = std::str::from_utf8(attr.key.as_ref()).unwrap_or("");
-                                let value = attr.unescape_value().unwrap_or_default().to_string();
-                                
-                                match key {
-                                    "id" => current_game.id = value,
-                                    "width" => current_game.width = value.parse().unwrap_or(800),
-                                    "height" => current_game.height = value.parse().unwrap_or(600),
-                                    _ => {}
-                                }
-                            }
+                Ok(Event::Eof) => break,
+                Ok(event) => {
+                    if let Some(game) = self.handle_event(&mut state, event, event_offset)? {
+                        if !on_game(self, game)? {
+                            break;
                         }
-                    } else if in_game {
-                        current_field = name_str.to_string();
                     }
                 }
-                Ok(Event::Text(e)) => {
-                    if in_game && !current_field.is_empty() {
-                        let text = e.unescape().unwrap_or_default().to_string();
-                        match current_field.as_str() {
-                            "name" => current_game.name = text,
-                            "description" => current_game.description = text,
-                            "thumbnail" => current_game.thumbnail = text,
-                            "url" => current_game.url = text,
-                            "category" => current_game.category = text,
-                            _ => {}
+                Err(e) => {
+                    let offset = reader.buffer_position();
+                    let (line, column) = line_column(xml_data, offset);
+                    return Err(format!("XML parse error at byte {} (line {}, column {}): {}", offset, line, column, e).into());
+                }
+            }
+            buf.clear();
+        }
+        
+        Ok(())
+    }
+
+    /// Advance `state` by one reader event found at byte `offset`, returning
+    /// the game it completes, if any
+    fn handle_event(&mut self, state: &mut ReadState, event: Event, offset: usize) -> Result<Option<GameInfo>, String> {
+        match event {
+            Event::Start(ref e) => {
+                let name_str = self.element_name(e.name());
+
+                if name_str == "game" && !state.in_game {
+                    state.in_game = true;
+                    state.game_offset = offset;
+                    state.current_game = self.begin_game(e, &state.open_tags);
+                } else if state.in_game {
+                    state.current_field = name_str.clone();
+                }
+                state.open_tags.push(name_str);
+            }
+            Event::Empty(ref e) if !state.in_game && self.element_name(e.name()) == "game" => {
+                // Self-closing `<game .../>` with every field in attributes
+                let game = self.begin_game(e, &state.open_tags);
+                return self.finish_game(game, format_args!("byte {}", offset));
+            }
+            Event::Text(e) if state.in_game && !state.current_field.is_empty() => {
+                let text = e.unescape().unwrap_or_default().to_string();
+                state.current_game.set_text_field(self.field_name(&state.current_field), text);
+            }
+            Event::End(ref e) => {
+                let name_str = self.element_name(e.name());
+                if state.open_tags.pop().as_deref() != Some(name_str.as_str()) {
+                    return Err(format!("XML parse error at byte {}: unexpected </{}>", offset, name_str));
+                }
+                state.current_field.clear();
+                if name_str == "game" && state.in_game {
+                    state.in_game = false;
+                    let game = std::mem::take(&mut state.current_game);
+                    return self.finish_game(game, format_args!("byte {}", state.game_offset));
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Parse as much of the buffered feed as is complete, adding its games
+    /// to the catalog. A tag or text cut off at the end of the buffer waits
+    /// for the next chunk, as does a syntax error, unless `at_end` says no
+    /// more input is coming.
+    fn drain_feed(&mut self, at_end: bool) -> Result<(), String> {
+        let pending = std::mem::take(&mut self.feed.pending);
+        let mut state = std::mem::take(&mut self.feed.state);
+        let consumed = self.feed.consumed;
+
+        // End tags are matched against `state`, which outlives this reader
+        let mut reader = Reader::from_str(&pending);
+        reader.trim_text(true);
+        reader.check_end_names(false);
+
+        let mut buf = Vec::new();
+        let mut done = 0;
+        let result = loop {
+            let event_offset = reader.buffer_position();
+            match reader.read_event_into(&mut buf) {
+                Ok(Event::Eof) => break Ok(()),
+                Ok(event) => {
+                    // Completeness is judged from the input, since quick-xml
+                    // hands back a tag cut off by the end of input (`<gam`,
+                    // `</`) as if it were whole and doesn't advance past text
+                    // at the end. A tag counts once its `>` has arrived, text
+                    // once the next `<` has
+                    let is_text = matches!(event, Event::Text(_));
+                    let (complete, end) = if is_text {
+                        match pending[event_offset..].find('<') {
+                            Some(len) => (true, event_offset + len),
+                            None => (false, pending.len()),
                         }
+                    } else {
+                        let end = reader.buffer_position();
+                        (pending.as_bytes()[..end].ends_with(b">"), end)
+                    };
+                    if !complete && !at_end {
+                        break Ok(());
                     }
-                }
-                Ok(Event::End(ref e)) => {
-                    let name = std::str::from_utf8(e.name().as_ref()).unwrap_or("");
-                    if name == "game" && in_game {
-                        if !current_game.id.is_empty() {
-                            self.games.push(current_game.clone());
+                    if !complete && !is_text {
+                        break Err(format!("XML parse error at byte {}: unclosed tag", consumed + event_offset));
+                    }
+                    match self.handle_event(&mut state, event, consumed + event_offset) {
+                        Ok(game) => {
+                            self.games.extend(game);
+                            done = end;
                         }
-                        in_game = false;
+                        Err(e) => break Err(e),
                     }
                 }
-                Ok(Event::Eof) => break,
-                Err(e) => return Err(JsValue::from_str(&format!("XML parse error: {}", e))),
-                _ => {}
+                Err(_) if !at_end => break Ok(()),
+                Err(e) => break Err(format!("XML parse error at byte {}: {}", consumed + reader.buffer_position(), e)),
             }
             buf.clear();
+        };
+
+        self.feed = FeedState {
+            pending: pending[done..].to_string(),
+            state,
+            consumed: consumed + done,
+        };
+        result
+    }
+
+    /// `feed` with the error as a plain message
+    fn feed_chunk(&mut self, chunk: &str) -> Result<(), String> {
+        self.feed.pending.push_str(chunk);
+        self.drain_feed(false)
+    }
+
+    /// `finish` with the error as a plain message
+    fn finish_feed(&mut self) -> Result<(), String> {
+        let result = self.drain_feed(true);
+        self.feed = FeedState::default();
+        result
+    }
+
+    /// `parse_json` with the error as a plain message
+    fn read_json(&mut self, json_data: &str) -> Result<(), String> {
+        let entries: Vec<JsonGame> = serde_json::from_str(json_data)
+            .map_err(|e| format!("JSON parse error: {}", e))?;
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            let mut game = entry.info;
+            game.width = entry.width.unwrap_or(self.default_width);
+            game.height = entry.height.unwrap_or(self.default_height);
+            if let Some(game) = self.finish_game(game, format_args!("index {}", index))? {
+                self.games.push(game);
+            }
         }
-        
         Ok(())
     }
+
+    /// Games where `query` matches any of `fields`, ranked by the first
+    /// field that matched so earlier fields win. Ties keep catalog order.
+    fn search(&self, query: &str, fields: &[&str], whole_word: bool) -> Result<Vec<&GameInfo>, String> {
+        if let Some(unknown) = fields.iter().find(|f| !TEXT_FIELDS.contains(f)) {
+            return Err(format!("Unknown game field: {}", unknown));
+        }
+
+        let query = query.to_lowercase();
+        let mut ranked: Vec<(usize, &GameInfo)> = self.games.iter()
+            .filter_map(|g| {
+                fields.iter()
+                    .position(|f| g.text_field(f).is_some_and(|text| text_matches(text, &query, whole_word)))
+                    .map(|rank| (rank, g))
+            })
+            .collect();
+        ranked.sort_by_key(|&(rank, _)| rank);
+        Ok(ranked.into_iter().map(|(_, g)| g).collect())
+    }
+
+    /// Games with a name or description word within `max_distance` edits of
+    /// `query`, closest first. Ties keep catalog order.
+    fn fuzzy_search(&self, query: &str, max_distance: usize) -> Vec<&GameInfo> {
+        let query = query.trim().to_lowercase();
+        let mut ranked: Vec<(usize, &GameInfo)> = self.games.iter()
+            .filter_map(|g| {
+                let text = format!("{} {}", g.name, g.description).to_lowercase();
+                text.split(|c: char| !c.is_alphanumeric())
+                    .filter(|word| !word.is_empty())
+                    .map(|word| levenshtein(&query, word))
+                    .min()
+                    .filter(|&distance| distance <= max_distance)
+                    .map(|distance| (distance, g))
+            })
+            .collect();
+        ranked.sort_by_key(|&(distance, _)| distance);
+        ranked.into_iter().map(|(_, g)| g).collect()
+    }
+
+    /// `to_xml` with the error as a plain message. Games sharing a section
+    /// are wrapped in that section's element, so re-parsing with the same
+    /// section tags registered restores it. A section that isn't a valid
+    /// element name is an error.
+    fn write_xml(&self) -> Result<String, String> {
+        let mut writer = Writer::new(Vec::new());
+        let xml_error = |e: quick_xml::Error| format!("XML write error: {}", e);
+
+        writer.write_event(Event::Start(BytesStart::new("games"))).map_err(xml_error)?;
+        for (i, game) in self.games.iter().enumerate() {
+            let section = (game.section != DEFAULT_SECTION).then_some(game.section.as_str());
+            let previous = i.checked_sub(1).map(|p| self.games[p].section.as_str());
+            let next = self.games.get(i + 1).map(|g| g.section.as_str());
+            if let Some(section) = section.filter(|&s| previous != Some(s)) {
+                if !is_xml_name(section) {
+                    return Err(format!("Section {:?} is not a valid XML element name", section));
+                }
+                writer.write_event(Event::Start(BytesStart::new(section))).map_err(xml_error)?;
+            }
+
+            let width = game.width.to_string();
+            let height = game.height.to_string();
+            let mut start = BytesStart::new("game");
+            start.push_attribute(("id", game.id.as_str()));
+            start.push_attribute(("width", width.as_str()));
+            start.push_attribute(("height", height.as_str()));
+            writer.write_event(Event::Start(start)).map_err(xml_error)?;
+            for field in TEXT_FIELDS {
+                let value = game.text_field(field).unwrap_or_default();
+                if value.is_empty() {
+                    continue;
+                }
+                writer.write_event(Event::Start(BytesStart::new(field))).map_err(xml_error)?;
+                writer.write_event(Event::Text(BytesText::new(value))).map_err(xml_error)?;
+                writer.write_event(Event::End(BytesEnd::new(field))).map_err(xml_error)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new("game"))).map_err(xml_error)?;
+
+            if let Some(section) = section.filter(|&s| next != Some(s)) {
+                writer.write_event(Event::End(BytesEnd::new(section))).map_err(xml_error)?;
+            }
+        }
+        writer.write_event(Event::End(BytesEnd::new("games"))).map_err(xml_error)?;
+
+        String::from_utf8(writer.into_inner()).map_err(|e| format!("XML write error: {}", e))
+    }
+
+    /// Every non-empty category, lowercased, sorted and de-duplicated
+    fn category_set(&self) -> BTreeSet<String> {
+        self.category_count_map().into_keys().collect()
+    }
+
+    /// Games per non-empty category, lowercased so that categories differing
+    /// only in case count together, as `filter_by_category` matches them
+    fn category_count_map(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for game in self.games.iter().filter(|g| !g.category.is_empty()) {
+            *counts.entry(game.category.to_lowercase()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Games whose category is any of `categories`, ignoring case
+    fn in_categories(&self, categories: &[String]) -> Vec<&GameInfo> {
+        let wanted: Vec<String> = categories.iter()
+            .map(|c| c.to_lowercase())
+            .collect();
+        self.games.iter()
+            .filter(|g| wanted.contains(&g.category.to_lowercase()))
+            .collect()
+    }
+
+    /// Page `page` of the catalog, `page_size` games per page
+    fn page(&self, page: usize, page_size: usize) -> Result<GamePage<'_>, String> {
+        if page_size == 0 {
+            return Err("page_size must be greater than 0".to_string());
+        }
+
+        let total = self.games.len();
+        let start = page.saturating_mul(page_size).min(total);
+        let end = start.saturating_add(page_size).min(total);
+
+        Ok(GamePage {
+            items: &self.games[start..end],
+            total,
+            page,
+            total_pages: total.div_ceil(page_size),
+        })
+    }
+
+    /// `parse_xml` with the error as a plain message
+    fn read_xml(&mut self, xml_data: &str) -> Result<(), String> {
+        self.read_games(xml_data, |parser, game| {
+            parser.games.push(game);
+            Ok::<_, String>(true)
+        })?;
+        self.last_feed_hash = Some(feed_hash(xml_data));
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl GameParser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            games: Vec::new(),
+            section_tags: Vec::new(),
+            strict: false,
+            skipped: 0,
+            field_aliases: HashMap::new(),
+            base_url: None,
+            namespace_aware: false,
+            default_width: DEFAULT_WIDTH,
+            default_height: DEFAULT_HEIGHT,
+            last_feed_hash: None,
+            feed: FeedState::default(),
+        }
+    }
     
+    /// Parse XML game data (streaming, zero-copy where possible)
+    pub fn parse_xml(&mut self, xml_data: &str) -> Result<(), JsValue> {
+        self.read_xml(xml_data).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Parse a JSON array of games into the same store as `parse_xml`.
+    /// Missing fields are left empty, except width and height which take
+    /// the default size; games without an id follow the strict setting.
+    pub fn parse_json(&mut self, json_data: &str) -> Result<(), JsValue> {
+        self.read_json(json_data).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Parse the next chunk of an XML feed arriving in pieces, adding every
+    /// game completed so far to the catalog. Chunks may split the feed
+    /// anywhere, including inside a tag; call `finish` after the last one.
+    /// Syntax errors surface once later chunks can't fix them, at the
+    /// latest from `finish`.
+    pub fn feed(&mut self, chunk: &str) -> Result<(), JsValue> {
+        self.feed_chunk(chunk).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// End a feed started with `feed`, parsing whatever is left buffered
+    pub fn finish(&mut self) -> Result<(), JsValue> {
+        self.finish_feed().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Serialize the catalog back to XML: a `<games>` root holding one
+    /// `<game id width height>` per game with its text fields as escaped
+    /// child elements. `parse_xml` reads the output back unchanged.
+    pub fn to_xml(&self) -> Result<String, JsValue> {
+        self.write_xml().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Parse XML game data, passing each game to `callback` as soon as it is
+    /// read instead of storing it. Returning `false` from the callback stops
+    /// parsing; any other return value continues.
+    pub fn parse_xml_streaming(&mut self, xml_data: &str, callback: js_sys::Function) -> Result<(), JsValue> {
+        self.read_games(xml_data, |_, game| {
+            let value = serde_wasm_bindgen::to_value(&game)
+                .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))?;
+            let keep_going = callback.call1(&JsValue::NULL, &value)?;
+            Ok(keep_going.as_bool() != Some(false))
+        })
+    }
+
+    /// Register an element name whose `<game>` descendants are tagged with
+    /// that section (e.g. `featured`)
+    pub fn add_section_tag(&mut self, tag: &str) {
+        if !self.section_tags.iter().any(|t| t == tag) {
+            self.section_tags.push(tag.to_string());
+        }
+    }
+
+    /// Treat `<alias>` elements as the canonical field (one of name,
+    /// description, thumbnail, url, category). Aliases are case-insensitive.
+    pub fn add_field_alias(&mut self, alias: &str, canonical: &str) -> Result<(), JsValue> {
+        if !TEXT_FIELDS.contains(&canonical) {
+            return Err(JsValue::from_str(&format!("Unknown game field: {}", canonical)));
+        }
+        self.field_aliases.insert(alias.to_lowercase(), canonical.to_string());
+        Ok(())
+    }
+
+    /// Ignore namespace prefixes on element and attribute names, so a feed
+    /// using `<g:game>` and `<g:name>` parses like one using `<game>`
+    pub fn set_namespace_aware(&mut self, namespace_aware: bool) {
+        self.namespace_aware = namespace_aware;
+    }
+
+    /// In strict mode a `<game>` without an id fails the parse instead of
+    /// being skipped
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Opt in to URL normalization: game `url` and `thumbnail` values are
+    /// trimmed and relative ones resolved against `base` (e.g. the feed's own
+    /// URL). `None` turns normalization back off.
+    pub fn set_base_url(&mut self, base: Option<String>) {
+        self.base_url = base;
+    }
+
+    /// Size given to games that omit `width`/`height` (800x600 by default),
+    /// e.g. a portrait size for a mobile catalog
+    pub fn set_default_size(&mut self, width: u32, height: u32) {
+        self.default_width = width;
+        self.default_height = height;
+    }
+
+    /// Number of games skipped for missing an id (non-strict mode only)
+    pub fn skipped_count(&self) -> usize {
+        self.skipped
+    }
+    
+    /// Drop every parsed game, any unfinished `feed` and reset
+    /// `skipped_count`, keeping settings such as section tags and aliases, so
+    /// one parser can handle feeds in turn
+    pub fn clear(&mut self) {
+        self.games.clear();
+        self.skipped = 0;
+        self.last_feed_hash = None;
+        self.feed = FeedState::default();
+    }
+
+    /// Whether `xml_data` differs from the last feed `parse_xml` read
+    /// successfully. Lets a poll loop skip identical feeds with one hash
+    pub fn needs_reparse(&self, xml_data: &str) -> bool {
+        self.last_feed_hash.as_deref() != Some(feed_hash(xml_data).as_str())
+    }
+
+    /// Append a copy of every game in `other` to this catalog
+    pub fn merge(&mut self, other: &GameParser) {
+        self.games.extend(other.games.iter().cloned());
+    }
+
     /// Get total number of parsed games
     pub fn game_count(&self) -> usize {
         self.games.len()
@@ -137,6 +833,55 @@ impl GameParser {
         serde_wasm_bindgen::to_value(slice)
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
+
+    /// Remove games whose id was already seen, keeping the first occurrence
+    /// (or the last with `keep_last`). Returns the number of games removed.
+    pub fn dedup_by_id(&mut self, keep_last: bool) -> usize {
+        let before = self.games.len();
+
+        if keep_last {
+            let last_index: HashMap<&str, usize> = self.games.iter()
+                .enumerate()
+                .map(|(i, g)| (g.id.as_str(), i))
+                .collect();
+            let keep: Vec<bool> = self.games.iter()
+                .enumerate()
+                .map(|(i, g)| last_index[g.id.as_str()] == i)
+                .collect();
+            let mut keep = keep.into_iter();
+            self.games.retain(|_| keep.next().unwrap_or(false));
+        } else {
+            let mut seen = HashSet::new();
+            self.games.retain(|g| seen.insert(g.id.clone()));
+        }
+
+        before - self.games.len()
+    }
+
+    /// Get a page of games together with `total`, `page` and `total_pages`
+    pub fn get_games_paged(&self, page: usize, page_size: usize) -> Result<JsValue, JsValue> {
+        let result = self.page(page, page_size).map_err(|e| JsValue::from_str(&e))?;
+        serde_wasm_bindgen::to_value(&result)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Sort games in place by `name`, `category` (both case-insensitive) or
+    /// `id`. The sort is stable, so equal keys keep their parsed order.
+    pub fn sort_games(&mut self, key: &str, ascending: bool) -> Result<(), JsValue> {
+        let compare: fn(&GameInfo, &GameInfo) -> std::cmp::Ordering = match key {
+            "name" => |a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            "category" => |a, b| a.category.to_lowercase().cmp(&b.category.to_lowercase()),
+            "id" => |a, b| a.id.cmp(&b.id),
+            _ => return Err(JsValue::from_str(&format!("Unknown sort key: {}", key))),
+        };
+
+        if ascending {
+            self.games.sort_by(compare);
+        } else {
+            self.games.sort_by(|a, b| compare(b, a));
+        }
+        Ok(())
+    }
     
     /// Search games by name
     pub fn search_games(&self, query: &str) -> Result<JsValue, JsValue> {
@@ -149,14 +894,503 @@ impl GameParser {
         serde_wasm_bindgen::to_value(&results)
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
-    
-    /// Filter games by category
+
+    /// Typo-tolerant search: games with a word in the name or description
+    /// within `max_distance` edits (Levenshtein) of `query`, closest first
+    pub fn search_games_fuzzy(&self, query: &str, max_distance: usize) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.fuzzy_search(query, max_distance))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Search only `fields` (name, description, thumbnail, url, category;
+    /// empty means name and description), optionally matching whole words
+    /// only. Results are ranked by the first listed field that matched, so
+    /// name matches come before description matches by default.
+    pub fn search_games_opts(&self, query: &str, fields: Vec<String>, whole_word: bool) -> Result<JsValue, JsValue> {
+        let fields: Vec<&str> = if fields.is_empty() {
+            vec!["name", "description"]
+        } else {
+            fields.iter().map(String::as_str).collect()
+        };
+        let results = self.search(query, &fields, whole_word).map_err(|e| JsValue::from_str(&e))?;
+
+        serde_wasm_bindgen::to_value(&results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Filter games by category (case-insensitive)
     pub fn filter_by_category(&self, category: &str) -> Result<JsValue, JsValue> {
-        let results: Vec<&GameInfo> = self.games.iter()
-            .filter(|g| g.category == category)
-            .collect();
-        
+        let results = self.in_categories(&[category.to_string()]);
+
+        serde_wasm_bindgen::to_value(&results)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Sorted, de-duplicated list of all non-empty categories, lowercased
+    pub fn categories(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.category_set())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Number of games per non-empty category, ignoring case
+    pub fn category_counts(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.category_count_map())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Filter games matching any of the given categories (case-insensitive)
+    pub fn filter_by_categories(&self, categories: Vec<String>) -> Result<JsValue, JsValue> {
+        let results = self.in_categories(&categories);
+
         serde_wasm_bindgen::to_value(&results)
             .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_are_tagged_with_their_section() {
+        let xml = r#"<catalog>
+            <games><game id="a"><name>A</name></game></games>
+            <featured><row><game id="b"><name>B</name></game></row><game id="c"></game></featured>
+        </catalog>"#;
+        let mut parser = GameParser::new();
+        parser.add_section_tag("featured");
+        parser.parse_xml(xml).unwrap();
+
+        assert_eq!(parser.game_count(), 3);
+        let sections: Vec<(&str, &str)> = parser.games.iter().map(|g| (g.id.as_str(), g.section.as_str())).collect();
+        assert_eq!(sections, [("a", "default"), ("b", "featured"), ("c", "featured")]);
+        assert_eq!(parser.games[1].name, "B");
+    }
+
+    #[test]
+    fn read_loop_hands_over_games_and_stops_when_asked() {
+        // What parse_xml_streaming does with a callback returning false on
+        // the second game
+        let xml = r#"<games><game id="a"></game><game id="b"></game><game id="c"></game></games>"#;
+        let mut parser = GameParser::new();
+        let mut seen = Vec::new();
+        parser.read_games(xml, |_, game| {
+            seen.push(game.id);
+            Ok::<_, String>(seen.len() < 2)
+        }).unwrap();
+
+        assert_eq!(seen, ["a", "b"]);
+        assert_eq!(parser.game_count(), 0);
+    }
+
+    // Calling back into JS needs a JS host, so this only runs under
+    // `wasm-bindgen-test-runner`
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn parse_xml_streaming_stops_when_the_callback_returns_false() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use wasm_bindgen::JsCast;
+
+        let xml = r#"<games><game id="a"></game><game id="b"></game><game id="c"></game></games>"#;
+        for (stop_after, expected) in [(1, vec!["a"]), (usize::MAX, vec!["a", "b", "c"])] {
+            let seen = Rc::new(RefCell::new(Vec::new()));
+            let log = seen.clone();
+            let callback = Closure::<dyn FnMut(JsValue) -> JsValue>::new(move |game: JsValue| {
+                let id = js_sys::Reflect::get(&game, &JsValue::from_str("id")).unwrap();
+                log.borrow_mut().push(id.as_string().unwrap());
+                // Anything but false, here undefined, keeps parsing
+                if log.borrow().len() >= stop_after { JsValue::FALSE } else { JsValue::UNDEFINED }
+            });
+
+            let mut parser = GameParser::new();
+            parser.parse_xml_streaming(xml, callback.as_ref().unchecked_ref::<js_sys::Function>().clone()).unwrap();
+            assert_eq!(*seen.borrow(), expected);
+            assert_eq!(parser.game_count(), 0);
+        }
+    }
+
+    #[test]
+    fn games_without_an_id_are_skipped_or_rejected() {
+        let xml = r#"<games><game id="a"><name>A</name></game><game><name>Nameless</name></game><game id="b"></game></games>"#;
+        let mut parser = GameParser::new();
+        parser.read_xml(xml).unwrap();
+        assert_eq!(parser.game_count(), 2);
+        assert_eq!(parser.skipped_count(), 1);
+
+        let mut strict = GameParser::new();
+        strict.set_strict(true);
+        let error = strict.read_xml(xml).unwrap_err();
+        assert!(error.contains("\"Nameless\""), "{}", error);
+        assert!(error.contains("has no id"), "{}", error);
+        assert_eq!(strict.skipped_count(), 0);
+    }
+
+    #[test]
+    fn aliased_elements_fill_canonical_fields() {
+        let xml = r#"<games><game id="a"><name>A</name><IMG>pic.png</IMG><Link>http://example.com/a</Link></game></games>"#;
+        let mut parser = GameParser::new();
+        parser.add_field_alias("img", "thumbnail").unwrap();
+        parser.add_field_alias("LINK", "url").unwrap();
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.games[0].thumbnail, "pic.png");
+        assert_eq!(parser.games[0].url, "http://example.com/a");
+    }
+
+    #[test]
+    fn sort_games_orders_by_each_key() {
+        let xml = r#"<games>
+            <game id="2"><name>beta</name><category>Puzzle</category></game>
+            <game id="1"><name>Alpha</name><category>racing</category></game>
+            <game id="3"><name>Gamma</name><category>action</category></game>
+        </games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let ids = |parser: &GameParser| parser.games.iter().map(|g| g.id.clone()).collect::<Vec<_>>();
+
+        for (key, ascending) in [("name", ["1", "2", "3"]), ("category", ["3", "2", "1"]), ("id", ["1", "2", "3"])] {
+            parser.sort_games(key, true).unwrap();
+            assert_eq!(ids(&parser), ascending, "{} ascending", key);
+            parser.sort_games(key, false).unwrap();
+            let descending: Vec<&str> = ascending.iter().rev().copied().collect();
+            assert_eq!(ids(&parser), descending, "{} descending", key);
+        }
+    }
+
+    #[test]
+    fn total_pages_rounds_up() {
+        let mut parser = GameParser::new();
+        let xml: String = (0..10).map(|i| format!(r#"<game id="{}"></game>"#, i)).collect();
+        parser.parse_xml(&format!("<games>{}</games>", xml)).unwrap();
+
+        let first = parser.page(0, 3).unwrap();
+        assert_eq!((first.total, first.total_pages, first.items.len()), (10, 4, 3));
+        let last = parser.page(3, 3).unwrap();
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.items[0].id, "9");
+        assert!(parser.page(4, 3).unwrap().items.is_empty());
+        assert!(parser.page(0, 0).is_err());
+    }
+
+    #[test]
+    fn self_closing_game_reads_every_field_from_attributes() {
+        let xml = r#"<games><game id="x" name="Foo" description="Desc" thumbnail="http://example.com/t.png"
+            url="http://example.com/x" category="puzzle" width="320" height="240"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+
+        assert_eq!(parser.game_count(), 1);
+        let game = &parser.games[0];
+        assert_eq!(
+            (game.id.as_str(), game.name.as_str(), game.description.as_str(), game.thumbnail.as_str(), game.url.as_str(), game.category.as_str()),
+            ("x", "Foo", "Desc", "http://example.com/t.png", "http://example.com/x", "puzzle")
+        );
+        assert_eq!((game.width, game.height), (320, 240));
+    }
+
+    #[test]
+    fn category_filters_ignore_case() {
+        let xml = r#"<games><game id="1" category="Puzzle"/><game id="2" category="puzzle"/>
+            <game id="3" category="RACING"/><game id="4" category="Action"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let ids = |games: Vec<&GameInfo>| games.iter().map(|g| g.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(ids(parser.in_categories(&["PUZZLE".to_string()])), ["1", "2"]);
+        assert_eq!(ids(parser.in_categories(&["puzzle".to_string(), "Racing".to_string()])), ["1", "2", "3"]);
+        assert!(parser.in_categories(&["sports".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn dedup_by_id_keeps_one_game_per_id() {
+        let xml = r#"<games><game id="a" name="first"/><game id="b" name="other"/><game id="a" name="second"/></games>"#;
+        let names = |parser: &GameParser| parser.games.iter().map(|g| g.name.clone()).collect::<Vec<_>>();
+
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.dedup_by_id(false), 1);
+        assert_eq!(names(&parser), ["first", "other"]);
+
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.dedup_by_id(true), 1);
+        assert_eq!(names(&parser), ["other", "second"]);
+    }
+
+    #[test]
+    fn categories_are_sorted_and_unique() {
+        let xml = r#"<games><game id="1" category="racing"/><game id="2" category="action"/>
+            <game id="3" category="Racing"/><game id="4"/><game id="5" category="puzzle"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.category_set().into_iter().collect::<Vec<_>>(), ["action", "puzzle", "racing"]);
+        assert_eq!(
+            parser.category_count_map().into_iter().collect::<Vec<_>>(),
+            [("action".to_string(), 1), ("puzzle".to_string(), 1), ("racing".to_string(), 2)]
+        );
+        assert_eq!(parser.in_categories(&["racing".to_string()]).len(), 2);
+    }
+
+    #[test]
+    fn validate_rejects_javascript_urls_and_missing_fields() {
+        let xml = r#"<games>
+            <game id="ok" name="Fine" url="https://example.com/play" thumbnail="http://example.com/t.png"/>
+            <game id="evil" name="Evil" url="javascript:alert(1)"/>
+            <game id="thumb" name="Thumb" url="https://example.com/" thumbnail="JavaScript:alert(1)"/>
+            <game id="blank" url="https://example.com/"/>
+        </games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let errors: Vec<Result<(), String>> = parser.games.iter().map(GameInfo::check).collect();
+
+        assert_eq!(errors[0], Ok(()));
+        assert!(errors[1].as_ref().unwrap_err().contains("non-http(s) url"), "{:?}", errors[1]);
+        assert!(errors[2].as_ref().unwrap_err().contains("non-http(s) thumbnail"), "{:?}", errors[2]);
+        assert!(errors[3].as_ref().unwrap_err().contains("empty name"), "{:?}", errors[3]);
+    }
+
+    #[test]
+    fn relative_urls_are_resolved_against_the_base() {
+        let xml = r#"<games><game id="a" name="A" thumbnail="  img/a.png ">
+            <url> ../play/a.html?x=1 </url></game></games>"#;
+        let mut parser = GameParser::new();
+        parser.set_base_url(Some("https://cdn.example.com/feeds/daily.xml".to_string()));
+        parser.parse_xml(xml).unwrap();
+
+        let game = &parser.games[0];
+        assert_eq!(game.url, "https://cdn.example.com/play/a.html?x=1");
+        assert_eq!(game.thumbnail, "https://cdn.example.com/feeds/img/a.png");
+        assert_eq!(game.check(), Ok(()));
+
+        let base = "https://example.com/a/b";
+        assert_eq!(resolve_url(base, "/root.html"), "https://example.com/root.html");
+        assert_eq!(resolve_url(base, "//other.com/x"), "https://other.com/x");
+        assert_eq!(resolve_url("https://example.com", "x.html"), "https://example.com/x.html");
+        assert_eq!(resolve_url(base, "javascript:alert(1)"), "javascript:alert(1)");
+    }
+
+    #[test]
+    fn prefixed_feed_parses_in_namespace_aware_mode() {
+        let xml = r#"<g:feed xmlns:g="http://example.com/ns"><g:featured>
+            <g:game g:id="a"><g:name>A</g:name><g:url>https://example.com/a</g:url></g:game>
+            <g:game g:id="b" g:width="320"/>
+        </g:featured></g:feed>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.game_count(), 0);
+
+        let mut parser = GameParser::new();
+        parser.set_namespace_aware(true);
+        parser.add_section_tag("featured");
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.game_count(), 2);
+        let game = &parser.games[0];
+        assert_eq!((game.id.as_str(), game.name.as_str(), game.url.as_str()), ("a", "A", "https://example.com/a"));
+        assert_eq!(game.section, "featured");
+        assert_eq!(parser.games[1].width, 320);
+    }
+
+    #[test]
+    fn parse_errors_report_their_position() {
+        let xml = "<games>\n  <game id=\"a\"></gmae>\n</games>";
+        let error = GameParser::new().read_xml(xml).unwrap_err();
+
+        let offset: usize = error.strip_prefix("XML parse error at byte ")
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(|n| n.parse().ok())
+            .unwrap_or_else(|| panic!("no byte offset in {:?}", error));
+        assert!(offset > 0, "{}", error);
+        assert!(error.contains("(line 2, column "), "{}", error);
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+    }
+
+    #[test]
+    fn merged_parsers_combine_catalogs_and_clear_resets() {
+        let mut first = GameParser::new();
+        first.parse_xml(r#"<games><game id="a"/><game id="b"/><game/></games>"#).unwrap();
+        let mut second = GameParser::new();
+        second.parse_xml(r#"<games><game id="c"/></games>"#).unwrap();
+
+        first.merge(&second);
+        assert_eq!(first.game_count(), 3);
+        assert_eq!(second.game_count(), 1);
+        let ids: Vec<&str> = first.games.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+
+        first.clear();
+        assert_eq!((first.game_count(), first.skipped_count()), (0, 0));
+        first.parse_xml(r#"<games><game id="d"/></games>"#).unwrap();
+        assert_eq!(first.game_count(), 1);
+    }
+
+    #[test]
+    fn missing_dimensions_use_the_configured_defaults() {
+        let xml = r#"<games><game id="a"/><game id="b" width="1280" height="720"/><game id="c" height="0"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.set_default_size(360, 640);
+        parser.parse_xml(xml).unwrap();
+
+        let sizes: Vec<(u32, u32)> = parser.games.iter().map(|g| (g.width, g.height)).collect();
+        assert_eq!(sizes, [(360, 640), (1280, 720), (360, 0)]);
+        assert_eq!(parser.games[0].aspect_ratio(), 0.5625);
+        assert_eq!(parser.games[1].aspect_ratio(), 16.0 / 9.0);
+        assert_eq!(parser.games[2].aspect_ratio(), 0.0);
+    }
+
+    #[test]
+    fn json_catalog_lands_in_the_same_store() {
+        let json = r#"[
+            {"id": "a", "name": "Alpha", "url": "https://example.com/a", "width": 320, "category": "puzzle"},
+            {"id": "b", "name": "Beta"},
+            {"name": "No id"}
+        ]"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(r#"<games><game id="x"/></games>"#).unwrap();
+        parser.parse_json(json).unwrap();
+
+        assert_eq!(parser.game_count(), 3);
+        assert_eq!(parser.skipped_count(), 1);
+        let games: Vec<(&str, u32, u32, &str)> = parser.games.iter()
+            .map(|g| (g.id.as_str(), g.width, g.height, g.section.as_str()))
+            .collect();
+        assert_eq!(games, [("x", 800, 600, "default"), ("a", 320, 600, "default"), ("b", 800, 600, "default")]);
+        assert_eq!(parser.games[1].category, "puzzle");
+        assert!(parser.read_json("{not json").unwrap_err().starts_with("JSON parse error"));
+    }
+
+    #[test]
+    fn search_can_be_scoped_to_fields_and_ranked() {
+        let xml = r#"<games>
+            <game id="1" name="Slither" description="A snake game, like agar.io" category="io"/>
+            <game id="2" name="Radio Tower" description="Build it up" category="puzzle"/>
+            <game id="3" name="Zombs.io" description="Survive" category="io"/>
+        </games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let ids = |games: Vec<&GameInfo>| games.iter().map(|g| g.id.clone()).collect::<Vec<_>>();
+
+        // Name matches rank ahead of description matches
+        assert_eq!(ids(parser.search("io", &["name", "description"], false).unwrap()), ["2", "3", "1"]);
+        assert_eq!(ids(parser.search("io", &["name"], false).unwrap()), ["2", "3"]);
+        assert_eq!(ids(parser.search("IO", &["category"], false).unwrap()), ["1", "3"]);
+        assert!(parser.search("io", &["publisher"], false).is_err());
+    }
+
+    #[test]
+    fn whole_word_search_ignores_partial_words() {
+        let xml = r#"<games><game id="1" name="Radio Tower"/><game id="2" name="Zombs.io"/>
+            <game id="3" name="Io Adventure"/><game id="4" name="Snake: The Game"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let ids = |games: Vec<&GameInfo>| games.iter().map(|g| g.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(ids(parser.search("io", &["name"], false).unwrap()), ["1", "2", "3"]);
+        assert_eq!(ids(parser.search("io", &["name"], true).unwrap()), ["2", "3"]);
+        assert_eq!(ids(parser.search("snake: the", &["name"], true).unwrap()), ["4"]);
+        assert!(parser.search("gam", &["name"], true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fuzzy_search_tolerates_a_typo() {
+        let xml = r#"<games><game id="1" name="Sky Runner" description="A fast platformer"/>
+            <game id="2" name="Platform" description="Jump around"/><game id="3" name="Chess"/></games>"#;
+        let mut parser = GameParser::new();
+        parser.parse_xml(xml).unwrap();
+        let ids = |games: Vec<&GameInfo>| games.iter().map(|g| g.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(levenshtein("platfrm", "platform"), 1);
+        assert_eq!(ids(parser.fuzzy_search("platfrm", 1)), ["2"]);
+        // "platformer" is three edits away, so it only shows up later
+        assert_eq!(ids(parser.fuzzy_search("Platfrm", 3)), ["2", "1"]);
+        assert!(parser.fuzzy_search("platfrm", 0).is_empty());
+    }
+
+    #[test]
+    fn to_xml_round_trips_the_catalog() {
+        let xml = r#"<catalog>
+            <game id="a" width="320" height="240"><name>Fish &amp; Chips</name><description>Use &lt;arrows&gt; "quotes"</description>
+                <url>https://example.com/a?x=1&amp;y=2</url><category>puzzle</category></game>
+            <featured><game id="b"><name>B</name><thumbnail>https://example.com/b.png</thumbnail></game><game id="c"/></featured>
+            <game id="d"><name>D</name></game>
+        </catalog>"#;
+        let mut parser = GameParser::new();
+        parser.add_section_tag("featured");
+        parser.parse_xml(xml).unwrap();
+        assert_eq!(parser.game_count(), 4);
+
+        let written = parser.write_xml().unwrap();
+        let mut reparsed = GameParser::new();
+        reparsed.add_section_tag("featured");
+        reparsed.parse_xml(&written).unwrap();
+        assert_eq!(reparsed.games, parser.games, "{}", written);
+
+        // A section that would inject markup is refused
+        parser.games[0].section = "x><script/><y".to_string();
+        assert!(parser.write_xml().unwrap_err().contains("not a valid XML element name"));
+    }
+
+    #[test]
+    fn identical_feeds_do_not_need_a_reparse() {
+        let xml = r#"<games><game id="1"><name>One</name></game></games>"#;
+        let mut parser = GameParser::new();
+        assert!(parser.needs_reparse(xml));
+
+        parser.parse_xml(xml).unwrap();
+        assert!(!parser.needs_reparse(xml));
+        assert!(parser.needs_reparse(r#"<games><game id="2"/></games>"#));
+        assert_eq!(feed_hash(xml).len(), 64);
+
+        // A failed parse keeps the last good hash; clear forgets it
+        assert!(parser.read_xml("<games><game id=\"3\"></gmae></games>").is_err());
+        assert!(!parser.needs_reparse(xml));
+        parser.clear();
+        assert!(parser.needs_reparse(xml));
+    }
+
+    #[test]
+    fn chunked_feed_matches_one_shot_parse() {
+        let xml = r#"<catalog>
+            <game id="1"><name>Fish &amp; Chips</name><category>puzzle</category></game>
+            <featured><game id="2" width="320"><name>Second</name></game><game id="3"/></featured>
+            <game><name>No id</name></game>
+        </catalog>"#;
+        let mut one_shot = GameParser::new();
+        one_shot.add_section_tag("featured");
+        one_shot.parse_xml(xml).unwrap();
+        assert_eq!(one_shot.game_count(), 3);
+
+        // Every split point, including inside tags, entities and attribute values
+        for split in 0..=xml.len() {
+            let mut chunked = GameParser::new();
+            chunked.add_section_tag("featured");
+            chunked.feed_chunk(&xml[..split]).unwrap();
+            chunked.feed_chunk(&xml[split..]).unwrap();
+            chunked.finish_feed().unwrap();
+            assert_eq!(chunked.games, one_shot.games, "split at {}", split);
+            assert_eq!(chunked.skipped_count(), 1);
+        }
+
+        // Chunks ending inside a start tag, an attribute value and an end tag
+        let mut chunked = GameParser::new();
+        for chunk in ["<catalo", "g>\n<game id=\"1", "\"><name>One</name></", "game>", "</catalog>"] {
+            chunked.feed_chunk(chunk).unwrap();
+        }
+        chunked.finish_feed().unwrap();
+        assert_eq!(chunked.game_count(), 1);
+        assert_eq!(chunked.games[0].name, "One");
+
+        // A game is added as soon as its end tag arrives, but not before
+        let mut parser = GameParser::new();
+        let game_end = xml.find("</game>").unwrap() + 7;
+        parser.feed_chunk(&xml[..game_end - 1]).unwrap();
+        assert_eq!(parser.game_count(), 0);
+        parser.feed_chunk(">").unwrap();
+        assert_eq!(parser.game_count(), 1);
+
+        let mut parser = GameParser::new();
+        assert!(parser.feed_chunk("<games><game id=\"1\"></gmae>\n").is_err());
+        parser.clear();
+        parser.feed_chunk("<games><game id=\"1\"").unwrap();
+        assert!(parser.finish_feed().is_err());
+    }
+}
@@ -1,5 +1,16 @@
 use wasm_bindgen::prelude::*;
 use sha2::{Sha256, Digest};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Byte range `[start, end)` of one chunk
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkBoundary {
+    pub start: usize,
+    pub end: usize,
+    pub index: usize,
+}
 
 /// Fast chunking for large files
 #[wasm_bindgen]
@@ -7,16 +18,33 @@ pub struct Chunker {
     chunk_size: usize,
 }
 
+impl Chunker {
+    /// `new` with the error as a plain message
+    fn try_new(chunk_size: usize) -> Result<Chunker, String> {
+        if chunk_size == 0 {
+            return Err("chunk_size must be greater than 0".to_string());
+        }
+        Ok(Self { chunk_size })
+    }
+
+    fn boundary(&self, index: usize, total_size: usize) -> ChunkBoundary {
+        let start = index * self.chunk_size;
+        let end = std::cmp::min(start + self.chunk_size, total_size);
+        ChunkBoundary { start, end, index }
+    }
+}
+
 #[wasm_bindgen]
 impl Chunker {
+    /// Create a chunker; `chunk_size` must be nonzero
     #[wasm_bindgen(constructor)]
-    pub fn new(chunk_size: usize) -> Self {
-        Self { chunk_size }
+    pub fn new(chunk_size: usize) -> Result<Chunker, JsValue> {
+        Self::try_new(chunk_size).map_err(|e| JsValue::from_str(&e))
     }
-    
-    /// Calculate number of chunks for given data size
+
+    /// Calculate number of chunks for given data size (0 for empty data)
     pub fn chunk_count(&self, data_size: usize) -> usize {
-        (data_size + self.chunk_size - 1) / self.chunk_size
+        data_size.div_ceil(self.chunk_size)
     }
     
     /// Get chunk boundaries (start, end) for chunk index
@@ -25,6 +53,363 @@ impl Chunker {
         let end = std::cmp::min(start + self.chunk_size, total_size);
         vec![start, end]
     }
+
+    /// Get the typed boundary for chunk index, failing if it's out of range
+    pub fn chunk_boundary(&self, chunk_index: usize, total_size: usize) -> Result<ChunkBoundary, JsValue> {
+        let count = self.chunk_count(total_size);
+        if chunk_index >= count {
+            return Err(JsValue::from_str(&format!(
+                "Chunk index {} out of range ({} chunks)",
+                chunk_index, count
+            )));
+        }
+
+        Ok(self.boundary(chunk_index, total_size))
+    }
+
+    /// Get every chunk's boundary for data of the given size
+    pub fn all_boundaries(&self, total_size: usize) -> Vec<ChunkBoundary> {
+        (0..self.chunk_count(total_size))
+            .map(|index| self.boundary(index, total_size))
+            .collect()
+    }
+
+    /// Split data into chunk byte arrays
+    pub fn split_into_chunks(&self, data: &[u8]) -> Vec<js_sys::Uint8Array> {
+        data.chunks(self.chunk_size)
+            .map(js_sys::Uint8Array::from)
+            .collect()
+    }
+
+    /// Hex hash of each chunk of data, in one pass
+    pub fn split_and_hash(&self, data: &[u8]) -> Vec<String> {
+        data.chunks(self.chunk_size)
+            .map(hash_chunk_hex)
+            .collect()
+    }
+}
+
+/// Hashes of data re-chunked at a new chunk size
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rechunked {
+    /// Hex hash of each chunk at the new size, in order
+    pub hashes: Vec<String>,
+    /// Indices into `hashes` of chunks that are neither identical to an old
+    /// chunk nor derivable from old chunks, so their bytes must be re-read
+    pub novel: Vec<usize>,
+    /// Indices into `hashes` of chunks not stored as-is but covering a
+    /// contiguous run of whole old chunks, which can be concatenated to
+    /// build them: they start on an old chunk boundary and end on one or at
+    /// the end of the data
+    pub derivable: Vec<usize>,
+}
+
+/// Hash `data` at `new_size` for migrating a dedup store off `old_size`,
+/// reporting which new chunks the store already holds, which it can build
+/// from old chunks and which are novel
+#[wasm_bindgen]
+pub fn rechunk(data: &[u8], old_size: usize, new_size: usize) -> Result<Rechunked, JsValue> {
+    rechunk_hashes(data, old_size, new_size).map_err(|e| JsValue::from_str(&e))
+}
+
+fn rechunk_hashes(data: &[u8], old_size: usize, new_size: usize) -> Result<Rechunked, String> {
+    if old_size == 0 || new_size == 0 {
+        return Err("chunk sizes must be greater than 0".to_string());
+    }
+    let old: HashSet<String> = data.chunks(old_size).map(hash_chunk_hex).collect();
+    let hashes: Vec<String> = data.chunks(new_size).map(hash_chunk_hex).collect();
+    let whole_old_chunks = |index: usize| {
+        let start = index * new_size;
+        let end = (start + new_size).min(data.len());
+        start.is_multiple_of(old_size) && (end.is_multiple_of(old_size) || end == data.len())
+    };
+    let (derivable, novel) = (0..hashes.len())
+        .filter(|&index| !old.contains(&hashes[index]))
+        .partition(|&index| whole_old_chunks(index));
+    Ok(Rechunked { hashes, novel, derivable })
+}
+
+/// Chunk hashes of a file split into fixed-size chunks, for resumable
+/// uploads: the client sends the manifest, the server answers with the
+/// hashes it already has, and `missing_chunks` says which chunks to send.
+/// Hashes are added one chunk at a time with `push_chunk`, so a file can be
+/// read and hashed in slices without holding all of it in memory.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub chunk_size: usize,
+    pub total_size: usize,
+    /// Hex hash of each chunk pushed so far, in file order
+    pub chunk_hashes: Vec<String>,
+}
+
+impl Manifest {
+    fn chunker(&self) -> Chunker {
+        Chunker { chunk_size: self.chunk_size }
+    }
+
+    /// Check the fields are consistent, e.g. after deserializing
+    fn check(&self) -> Result<(), String> {
+        if self.chunk_size == 0 {
+            return Err("chunk_size must be greater than 0".to_string());
+        }
+        let count = self.chunker().chunk_count(self.total_size);
+        if self.chunk_hashes.len() > count {
+            return Err(format!(
+                "Manifest has {} chunk hashes but {} bytes only make {} chunks",
+                self.chunk_hashes.len(), self.total_size, count
+            ));
+        }
+        Ok(())
+    }
+
+    fn add_chunk(&mut self, chunk: &[u8]) -> Result<(), String> {
+        let index = self.chunk_hashes.len();
+        let count = self.chunker().chunk_count(self.total_size);
+        if index >= count {
+            return Err(format!("Manifest already has all {} chunks", count));
+        }
+        let expected = self.chunker().boundary(index, self.total_size);
+        if chunk.len() != expected.end - expected.start {
+            return Err(format!(
+                "Chunk {} is {} bytes, expected {}",
+                index, chunk.len(), expected.end - expected.start
+            ));
+        }
+        self.chunk_hashes.push(hash_chunk_hex(chunk));
+        Ok(())
+    }
+
+    /// Indices of chunks whose hash isn't in `have`
+    fn missing(&self, have: &[String]) -> Vec<usize> {
+        let have: HashSet<String> = have.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self.chunk_hashes.iter()
+            .enumerate()
+            .filter(|(_, hash)| !have.contains(hash.as_str()))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl Manifest {
+    /// Start an empty manifest for `total_size` bytes split into
+    /// `chunk_size`-byte chunks
+    #[wasm_bindgen(constructor)]
+    pub fn new(chunk_size: usize, total_size: usize) -> Result<Manifest, JsValue> {
+        let manifest = Self { chunk_size, total_size, chunk_hashes: Vec::new() };
+        manifest.check()?;
+        Ok(manifest)
+    }
+
+    /// Hash the next chunk of the file. Every chunk but the last must be
+    /// exactly `chunk_size` bytes.
+    pub fn push_chunk(&mut self, chunk: &[u8]) -> Result<(), JsValue> {
+        self.add_chunk(chunk).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Whether every chunk has been hashed
+    pub fn is_complete(&self) -> bool {
+        self.chunk_hashes.len() == self.chunker().chunk_count(self.total_size)
+    }
+
+    /// Byte range of chunk `index`, to resume reading the file from there
+    pub fn chunk_boundary(&self, index: usize) -> Result<ChunkBoundary, JsValue> {
+        self.chunker().chunk_boundary(index, self.total_size)
+    }
+
+    /// Indices of chunks whose hash isn't in `have` (e.g. the hashes the
+    /// server already stores), in file order
+    pub fn missing_chunks(&self, have: Vec<String>) -> Vec<usize> {
+        self.missing(&have)
+    }
+
+    /// Serialize as JSON (`chunk_size`, `total_size`, `chunk_hashes`)
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(self)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+    }
+
+    /// Read a manifest written by `to_json`
+    pub fn from_json(json: &str) -> Result<Manifest, JsValue> {
+        let manifest: Manifest = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid manifest JSON: {}", e)))?;
+        manifest.check()?;
+        Ok(manifest)
+    }
+}
+
+/// Bloom filter over chunk hashes: a compact "have we seen this chunk"
+/// pre-check before the authoritative index lookup. `might_contain` never
+/// misses an inserted hash but can report false positives at roughly the
+/// configured rate.
+#[wasm_bindgen]
+pub struct ChunkBloom {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+}
+
+impl ChunkBloom {
+    /// Two independent 64-bit hashes of a chunk hash. SHA-256 hex is
+    /// already uniform, so its first 16 bytes are used directly; anything
+    /// else is hashed first.
+    fn base_hashes(hash_hex: &str) -> (u64, u64) {
+        let bytes = hex_to_bytes(hash_hex)
+            .filter(|bytes| bytes.len() >= 16)
+            .unwrap_or_else(|| hash_chunk(hash_hex.as_bytes()));
+        let word = |at: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[at..at + 8]);
+            u64::from_le_bytes(word)
+        };
+        (word(0), word(8) | 1)
+    }
+
+    /// Bit positions for a hash, by double hashing `h1 + i * h2`
+    fn positions(&self, hash_hex: &str) -> impl Iterator<Item = u64> {
+        let (h1, h2) = Self::base_hashes(hash_hex);
+        let bit_count = self.bit_count;
+        (0..self.hash_count as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bit_count)
+    }
+}
+
+#[wasm_bindgen]
+impl ChunkBloom {
+    /// Size the filter for `expected_items` hashes at the given false
+    /// positive rate (between 0 and 1, exclusive)
+    #[wasm_bindgen(constructor)]
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Result<ChunkBloom, JsValue> {
+        if expected_items == 0 {
+            return Err(JsValue::from_str("expected_items must be greater than 0"));
+        }
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(JsValue::from_str(&format!(
+                "false_positive_rate must be between 0 and 1, got {}",
+                false_positive_rate
+            )));
+        }
+
+        // Optimal size m = -n ln p / (ln 2)^2 and hash count k = m/n ln 2
+        let n = expected_items as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-n * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hash_count = ((bit_count as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+
+        Ok(Self {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+        })
+    }
+
+    /// Record a hex chunk hash
+    pub fn insert(&mut self, hash_hex: &str) {
+        let positions: Vec<u64> = self.positions(hash_hex).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// False means the hash was definitely never inserted; true means it
+    /// probably was
+    pub fn might_contain(&self, hash_hex: &str) -> bool {
+        self.positions(hash_hex)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Size of the bit array
+    pub fn bit_count(&self) -> u64 {
+        self.bit_count
+    }
+
+    /// Bits set (and checked) per hash
+    pub fn hash_count(&self) -> u32 {
+        self.hash_count
+    }
+}
+
+/// Gear table for the content-defined chunker's rolling hash: 256
+/// pseudo-random values generated with splitmix64 so the table is fixed
+/// across builds
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Content-defined chunking: boundaries are placed where the high bits of a
+/// rolling (gear) hash of the last 64 bytes are all zero, so an insert or
+/// delete only moves the boundaries near the edit instead of every later one
+#[wasm_bindgen]
+pub struct ContentDefinedChunker {
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+}
+
+#[wasm_bindgen]
+impl ContentDefinedChunker {
+    /// Create a chunker with the given size limits. `avg_size` is rounded
+    /// up to a power of two.
+    #[wasm_bindgen(constructor)]
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Result<ContentDefinedChunker, JsValue> {
+        if min_size == 0 || min_size > avg_size || avg_size > max_size {
+            return Err(JsValue::from_str(&format!(
+                "Chunk sizes must satisfy 0 < min <= avg <= max, got {}/{}/{}",
+                min_size, avg_size, max_size
+            )));
+        }
+
+        // The hash shifts left once per byte, so its top bits mix in the
+        // most bytes (the low bits only see the last few)
+        let bits = avg_size.next_power_of_two().trailing_zeros();
+        let mask = if bits == 0 { 0 } else { u64::MAX << (64 - bits) };
+        Ok(Self { min_size, max_size, mask })
+    }
+
+    /// Find content-defined chunk boundaries for data
+    pub fn split(&self, data: &[u8]) -> Vec<ChunkBoundary> {
+        let mut boundaries = Vec::new();
+        let mut start = 0;
+
+        while start < data.len() {
+            let end = start + self.next_cut(&data[start..]);
+            boundaries.push(ChunkBoundary { start, end, index: boundaries.len() });
+            start = end;
+        }
+
+        boundaries
+    }
+}
+
+impl ContentDefinedChunker {
+    /// Length of the next chunk at the start of `data`
+    fn next_cut(&self, data: &[u8]) -> usize {
+        let limit = data.len().min(self.max_size);
+        if limit <= self.min_size {
+            return limit;
+        }
+
+        let mut hash: u64 = 0;
+        for (i, &byte) in data.iter().enumerate().take(limit) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            if i + 1 >= self.min_size && hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        limit
+    }
 }
 
 /// Fast SHA-256 hashing for chunk deduplication
@@ -38,27 +423,43 @@ pub fn hash_chunk(data: &[u8]) -> Vec<u8> {
 /// Hash chunk and return hex string
 #[wasm_bindgen]
 pub fn hash_chunk_hex(data: &[u8]) -> String {
-    let hash = hash_chunk(data);
-    hash.iter()
-        .map(|b| format!("{:02x}", b))
-        .collect()
+    bytes_to_hex(&hash_chunk(data))
 }
 
 /// Parallel chunk hashing (processes multiple chunks)
 #[wasm_bindgen]
 pub fn hash_chunks_batch(chunks: Vec<JsValue>) -> Result<Vec<String>, JsValue> {
-    let mut hashes = Vec::new();
-    
+    // Copy chunks out of JS first; JsValues can't be shared across threads
+    let mut decoded = Vec::with_capacity(chunks.len());
+
     for chunk_val in chunks {
-        if let Ok(bytes) = js_sys::Uint8Array::try_from(chunk_val) {
-            let data = bytes.to_vec();
-            hashes.push(hash_chunk_hex(&data));
+        if let Some(bytes) = chunk_val.dyn_ref::<js_sys::Uint8Array>() {
+            decoded.push(bytes.to_vec());
         } else {
             return Err(JsValue::from_str("Invalid chunk data"));
         }
     }
-    
-    Ok(hashes)
+
+    Ok(hash_chunks_hex(&decoded))
+}
+
+/// Hex hash of each chunk, in input order. With the `parallel` feature the
+/// chunks are spread across the rayon thread pool (wasm threads in the
+/// browser), falling back to sequential hashing when only one thread exists.
+#[cfg(feature = "parallel")]
+fn hash_chunks_hex(chunks: &[Vec<u8>]) -> Vec<String> {
+    use rayon::prelude::*;
+
+    if rayon::current_num_threads() > 1 {
+        chunks.par_iter().map(|chunk| hash_chunk_hex(chunk)).collect()
+    } else {
+        chunks.iter().map(|chunk| hash_chunk_hex(chunk)).collect()
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn hash_chunks_hex(chunks: &[Vec<u8>]) -> Vec<String> {
+    chunks.iter().map(|chunk| hash_chunk_hex(chunk)).collect()
 }
 
 /// Calculate content-addressable key for data
@@ -67,9 +468,552 @@ pub fn content_address(data: &[u8]) -> String {
     hash_chunk_hex(data)
 }
 
+/// Compare without returning early, so timing doesn't reveal where the
+/// first mismatch is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Incremental content addressing: feeding data in any split produces the
+/// same key as `content_address` over the concatenation
+#[wasm_bindgen]
+pub struct RollingHasher {
+    hasher: Sha256,
+}
+
+impl Default for RollingHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl RollingHasher {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { hasher: Sha256::new() }
+    }
+
+    /// Add the next piece of data
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Finish hashing and return the content address
+    pub fn finalize_hex(self) -> String {
+        bytes_to_hex(&self.hasher.finalize())
+    }
+}
+
+/// Content addressing for data piped through chunk by chunk, e.g. from a
+/// `ReadableStream` reader, so an upload never has to be buffered whole.
+/// Unlike `RollingHasher`, `finish` leaves the addresser ready for the
+/// next stream instead of consuming it.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct StreamingAddresser {
+    hasher: RollingHasher,
+}
+
+#[wasm_bindgen]
+impl StreamingAddresser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next chunk of the stream
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Content address of everything passed to `update` since the last
+    /// `finish`, which starts a new stream
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.hasher).finalize_hex()
+    }
+}
+
 /// Verify chunk integrity
 #[wasm_bindgen]
 pub fn verify_chunk(data: &[u8], expected_hash: &str) -> bool {
     let actual_hash = hash_chunk_hex(data);
-    actual_hash == expected_hash
+    constant_time_eq(actual_hash.as_bytes(), expected_hash.as_bytes())
+}
+
+/// Verify chunk integrity against a raw 32-byte SHA-256 digest.
+/// Returns false for digests of any other length.
+#[wasm_bindgen]
+pub fn verify_chunk_bytes(data: &[u8], expected_hash: &[u8]) -> bool {
+    constant_time_eq(&hash_chunk(data), expected_hash)
+}
+
+/// Decode a hex string, or `None` if it isn't valid hex
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_leaves(chunk_hashes: &[String]) -> Result<Vec<Vec<u8>>, JsValue> {
+    chunk_hashes.iter()
+        .map(|h| hex_to_bytes(h).ok_or_else(|| JsValue::from_str(&format!("Invalid chunk hash: {}", h))))
+        .collect()
+}
+
+fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+/// Hash each adjacent pair of nodes, pairing the last node with itself
+/// when the count is odd
+fn merkle_parent_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level.chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
+}
+
+/// Merkle root over hex chunk hashes. A single chunk's root is its own
+/// hash; an empty list gives the hash of empty data.
+#[wasm_bindgen]
+pub fn merkle_root(chunk_hashes: Vec<String>) -> Result<String, JsValue> {
+    let mut level = decode_leaves(&chunk_hashes)?;
+    if level.is_empty() {
+        return Ok(hash_chunk_hex(&[]));
+    }
+
+    while level.len() > 1 {
+        level = merkle_parent_level(&level);
+    }
+    Ok(bytes_to_hex(&level[0]))
+}
+
+/// Sibling hashes from leaf `index` up to the root
+#[wasm_bindgen]
+pub fn merkle_proof(chunk_hashes: Vec<String>, index: usize) -> Result<Vec<String>, JsValue> {
+    if index >= chunk_hashes.len() {
+        return Err(JsValue::from_str(&format!(
+            "Leaf index {} out of range ({} leaves)",
+            index, chunk_hashes.len()
+        )));
+    }
+
+    let mut level = decode_leaves(&chunk_hashes)?;
+    let mut position = index;
+    let mut proof = Vec::new();
+
+    while level.len() > 1 {
+        let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+        proof.push(bytes_to_hex(sibling));
+        level = merkle_parent_level(&level);
+        position /= 2;
+    }
+    Ok(proof)
+}
+
+/// Check that `leaf` at `index` hashes up to `root` through `proof`
+#[wasm_bindgen]
+pub fn verify_merkle_proof(leaf: &str, proof: Vec<String>, index: usize, root: &str) -> bool {
+    let Some(mut current) = hex_to_bytes(leaf) else {
+        return false;
+    };
+    let mut position = index;
+
+    for sibling in &proof {
+        let Some(sibling) = hex_to_bytes(sibling) else {
+            return false;
+        };
+        current = if position.is_multiple_of(2) {
+            hash_pair(&current, &sibling)
+        } else {
+            hash_pair(&sibling, &current)
+        };
+        position /= 2;
+    }
+
+    bytes_to_hex(&current) == root.to_ascii_lowercase()
+}
+
+const DELTA_COPY: u8 = 0;
+const DELTA_INSERT: u8 = 1;
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize, JsValue> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or_else(|| JsValue::from_str("Truncated chunk delta"))?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return Err(JsValue::from_str("Chunk delta varint overflow"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Encode `new` as copy/insert operations against `old`.
+///
+/// The delta is a sequence of ops: `0, offset, len` copies `len` bytes of
+/// `old` from `offset`; `1, len, bytes...` inserts literal bytes (numbers
+/// are LEB128 varints). The encoder keeps the common prefix and suffix as
+/// copies and inserts the differing middle, which suits a single edit.
+#[wasm_bindgen]
+pub fn chunk_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let prefix = old.iter()
+        .zip(new)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut delta = Vec::new();
+    if prefix > 0 {
+        delta.push(DELTA_COPY);
+        write_varint(&mut delta, 0);
+        write_varint(&mut delta, prefix);
+    }
+    let middle = &new[prefix..new.len() - suffix];
+    if !middle.is_empty() {
+        delta.push(DELTA_INSERT);
+        write_varint(&mut delta, middle.len());
+        delta.extend_from_slice(middle);
+    }
+    if suffix > 0 {
+        delta.push(DELTA_COPY);
+        write_varint(&mut delta, old.len() - suffix);
+        write_varint(&mut delta, suffix);
+    }
+
+    delta
+}
+
+/// Rebuild the new chunk from `old` and a delta from `chunk_delta`
+#[wasm_bindgen]
+pub fn apply_chunk_delta(old: &[u8], delta: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let mut output = Vec::new();
+    let mut pos = 0;
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            DELTA_COPY => {
+                let offset = read_varint(delta, &mut pos)?;
+                let len = read_varint(delta, &mut pos)?;
+                let source = offset.checked_add(len)
+                    .and_then(|end| old.get(offset..end))
+                    .ok_or_else(|| JsValue::from_str("Chunk delta copy is out of range"))?;
+                output.extend_from_slice(source);
+            }
+            DELTA_INSERT => {
+                let len = read_varint(delta, &mut pos)?;
+                let literal = pos.checked_add(len)
+                    .and_then(|end| delta.get(pos..end))
+                    .ok_or_else(|| JsValue::from_str("Truncated chunk delta"))?;
+                output.extend_from_slice(literal);
+                pos += len;
+            }
+            _ => return Err(JsValue::from_str(&format!("Unknown chunk delta op: {}", op))),
+        }
+    }
+
+    Ok(output)
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ten_bytes_in_chunks_of_four_have_three_boundaries() {
+        let chunker = Chunker::new(4).unwrap();
+        let boundaries = chunker.all_boundaries(10);
+        let ranges: Vec<(usize, usize, usize)> = boundaries.iter().map(|b| (b.index, b.start, b.end)).collect();
+        assert_eq!(ranges, [(0, 0, 4), (1, 4, 8), (2, 8, 10)]);
+        assert_eq!(chunker.chunk_boundary(2, 10).unwrap(), boundaries[2]);
+        assert_eq!(chunker.chunk_boundaries(2, 10), [8, 10]);
+    }
+
+    #[test]
+    fn empty_data_has_no_chunks() {
+        let chunker = Chunker::new(4).unwrap();
+        assert_eq!(chunker.chunk_count(0), 0);
+        assert!(chunker.all_boundaries(0).is_empty());
+        assert!(chunker.split_and_hash(&[]).is_empty());
+    }
+
+    #[test]
+    fn zero_chunk_size_is_rejected() {
+        assert_eq!(Chunker::try_new(0).err().as_deref(), Some("chunk_size must be greater than 0"));
+        assert_eq!(Chunker::try_new(1).unwrap().chunk_count(3), 3);
+    }
+
+    /// Incompressible bytes from a fixed-seed xorshift generator
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut seed = 0x9e3779b97f4a7c15u64;
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect()
+    }
+
+    #[test]
+    fn front_insert_keeps_most_content_defined_chunks() {
+        let data = random_bytes(64 * 1024);
+        let edited = [&[0xab][..], &data].concat();
+        let hashes = |chunks: Vec<ChunkBoundary>, data: &[u8]| -> HashSet<String> {
+            chunks.iter().map(|c| hash_chunk_hex(&data[c.start..c.end])).collect()
+        };
+
+        let cdc = ContentDefinedChunker::new(256, 1024, 4096).unwrap();
+        let before = hashes(cdc.split(&data), &data);
+        let after = hashes(cdc.split(&edited), &edited);
+        let kept = before.intersection(&after).count();
+        assert!(kept * 10 >= before.len() * 9, "{} of {} chunks kept", kept, before.len());
+
+        let fixed = Chunker::new(1024).unwrap();
+        let before: HashSet<String> = fixed.split_and_hash(&data).into_iter().collect();
+        let after: HashSet<String> = fixed.split_and_hash(&edited).into_iter().collect();
+        assert_eq!(before.intersection(&after).count(), 0);
+    }
+
+    #[test]
+    fn cut_depends_on_bytes_well_before_it() {
+        let cdc = ContentDefinedChunker::new(64, 1024, 4096).unwrap();
+        let mut data = random_bytes(8 * 1024);
+        let cut = cdc.split(&data)[0].end;
+        assert!(cut > 64 + 40);
+
+        // A changed byte can always add an earlier cut; only a hash that
+        // still sees it 40 bytes later can remove this one
+        let moved_later = (0..=255u8).any(|byte| {
+            data[cut - 40] = byte;
+            cdc.split(&data)[0].end > cut
+        });
+        assert!(moved_later);
+    }
+
+    #[test]
+    fn batch_hashes_match_sequential_hashes() {
+        let chunks: Vec<Vec<u8>> = (0..64).map(|i| random_bytes(100 + i)).collect();
+        let sequential: Vec<String> = chunks.iter().map(|c| hash_chunk_hex(c)).collect();
+        assert_eq!(hash_chunks_hex(&chunks), sequential);
+    }
+
+    #[test]
+    fn merkle_proofs_verify_for_one_to_three_leaves() {
+        let leaves: Vec<String> = ["a", "b", "c"].iter().map(|s| hash_chunk_hex(s.as_bytes())).collect();
+        let node = |left: &str, right: &str| bytes_to_hex(&hash_pair(&hex_to_bytes(left).unwrap(), &hex_to_bytes(right).unwrap()));
+        let ab = node(&leaves[0], &leaves[1]);
+        let expected_roots = [leaves[0].clone(), ab.clone(), node(&ab, &node(&leaves[2], &leaves[2]))];
+
+        for count in 1..=3 {
+            let leaves = leaves[..count].to_vec();
+            let root = merkle_root(leaves.clone()).unwrap();
+            assert_eq!(root, expected_roots[count - 1]);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(leaves.clone(), index).unwrap();
+                assert!(verify_merkle_proof(leaf, proof.clone(), index, &root), "{} leaves, leaf {}", count, index);
+                assert!(!verify_merkle_proof(&hash_chunk_hex(b"x"), proof, index, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn verify_chunk_checks_hex_and_raw_digests() {
+        let data = b"chunk contents";
+        let digest = hash_chunk(data);
+        assert!(verify_chunk(data, &hash_chunk_hex(data)));
+        assert!(!verify_chunk(data, &hash_chunk_hex(b"other contents")));
+        assert!(!verify_chunk(data, &hash_chunk_hex(data)[..62]));
+
+        assert!(verify_chunk_bytes(data, &digest));
+        assert!(!verify_chunk_bytes(b"other contents", &digest));
+        assert!(!verify_chunk_bytes(data, &digest[..31]));
+        assert!(!verify_chunk_bytes(data, &[digest.as_slice(), &[0]].concat()));
+    }
+
+    #[test]
+    fn chunk_delta_round_trips_a_short_insert() {
+        let old = random_bytes(4096);
+        let new = [&old[..1000], b"inserted run", &old[1000..]].concat();
+        let delta = chunk_delta(&old, &new);
+        assert!(delta.len() < new.len() / 10, "{} byte delta", delta.len());
+        assert_eq!(apply_chunk_delta(&old, &delta).unwrap(), new);
+
+        assert_eq!(apply_chunk_delta(&old, &chunk_delta(&old, &old)).unwrap(), old);
+        assert_eq!(apply_chunk_delta(&old, &chunk_delta(&old, &[])).unwrap(), b"");
+        assert_eq!(apply_chunk_delta(&[], &chunk_delta(&[], &new)).unwrap(), new);
+    }
+
+    #[test]
+    fn rolling_hasher_matches_content_address() {
+        let data = random_bytes(1001);
+        let (first, second) = data.split_at(400);
+        let mut hasher = RollingHasher::new();
+        hasher.update(first);
+        hasher.update(second);
+        assert_eq!(hasher.finalize_hex(), content_address(&data));
+    }
+
+    #[test]
+    fn split_and_hash_hashes_each_chunk() {
+        let data: Vec<u8> = (0..10).collect();
+        let chunker = Chunker::new(4).unwrap();
+        let expected: Vec<String> = [&data[0..4], &data[4..8], &data[8..10]].iter().map(|c| hash_chunk_hex(c)).collect();
+        assert_eq!(chunker.split_and_hash(&data), expected);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn split_into_chunks_returns_the_chunk_bytes() {
+        let data: Vec<u8> = (0..10).collect();
+        let chunks: Vec<Vec<u8>> = Chunker::new(4).unwrap().split_into_chunks(&data).iter().map(|c| c.to_vec()).collect();
+        assert_eq!(chunks, [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+    }
+
+    #[test]
+    fn manifest_reports_chunks_the_server_lacks() {
+        let data = random_bytes(10_000);
+        let mut manifest = Manifest::new(1024, data.len()).unwrap();
+        for chunk in data.chunks(1024) {
+            assert!(!manifest.is_complete());
+            manifest.add_chunk(chunk).unwrap();
+        }
+        assert!(manifest.is_complete());
+        assert_eq!(manifest.chunk_hashes, Chunker::new(1024).unwrap().split_and_hash(&data));
+
+        // The server has every even chunk
+        let have: Vec<String> = manifest.chunk_hashes.iter().step_by(2).cloned().collect();
+        assert_eq!(manifest.missing(&have), [1, 3, 5, 7, 9]);
+        assert_eq!(manifest.missing(&manifest.chunk_hashes), Vec::<usize>::new());
+        assert_eq!(manifest.missing(&[]).len(), 10);
+    }
+
+    #[test]
+    fn manifest_rejects_misaligned_chunks() {
+        let mut manifest = Manifest::new(4, 10).unwrap();
+        assert_eq!(manifest.add_chunk(&[0; 3]).err().as_deref(), Some("Chunk 0 is 3 bytes, expected 4"));
+        manifest.add_chunk(&[0; 4]).unwrap();
+        manifest.add_chunk(&[1; 4]).unwrap();
+        manifest.add_chunk(&[2; 2]).unwrap();
+        assert_eq!(manifest.add_chunk(&[3; 2]).err().as_deref(), Some("Manifest already has all 3 chunks"));
+
+        let mut tampered = manifest.clone();
+        tampered.total_size = 4;
+        assert!(tampered.check().is_err());
+    }
+
+    #[test]
+    fn manifest_round_trips_through_json() {
+        let mut manifest = Manifest::new(4, 6).unwrap();
+        manifest.add_chunk(b"abcd").unwrap();
+        let json = manifest.to_json().unwrap();
+        assert_eq!(Manifest::from_json(&json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        let mut bloom = ChunkBloom::new(5000, 0.01).unwrap();
+        let hashes: Vec<String> = (0..5000u32).map(|i| hash_chunk_hex(&i.to_le_bytes())).collect();
+        for hash in &hashes {
+            bloom.insert(hash);
+        }
+        assert!(hashes.iter().all(|hash| bloom.might_contain(hash)));
+        // Case and non-hash strings work too
+        bloom.insert("not-a-hash");
+        assert!(bloom.might_contain("not-a-hash"));
+        assert!(bloom.might_contain(&hashes[0].to_ascii_uppercase()));
+    }
+
+    #[test]
+    fn bloom_false_positive_rate_is_near_the_target() {
+        let mut bloom = ChunkBloom::new(10_000, 0.01).unwrap();
+        assert_eq!(bloom.hash_count(), 7);
+        for i in 0..10_000u32 {
+            bloom.insert(&hash_chunk_hex(&i.to_le_bytes()));
+        }
+
+        let false_positives = (10_000..30_000u32)
+            .filter(|i| bloom.might_contain(&hash_chunk_hex(&i.to_le_bytes())))
+            .count();
+        let rate = false_positives as f64 / 20_000.0;
+        assert!(rate < 0.02, "false positive rate {}", rate);
+    }
+
+    #[test]
+    fn rechunking_at_4_and_8_reports_novel_and_derivable_chunks() {
+        // Chunks at size 4: "abcd" "abcd" "efgh" "ij"; at size 8: "abcdabcd" "efghij"
+        let data = b"abcdabcdefghij";
+        let small = rechunk_hashes(data, 8, 4).unwrap();
+        let large = rechunk_hashes(data, 4, 8).unwrap();
+        assert_eq!(small.hashes, Chunker::new(4).unwrap().split_and_hash(data));
+        assert_eq!(large.hashes, vec![hash_chunk_hex(b"abcdabcd"), hash_chunk_hex(b"efghij")]);
+        assert!(large.hashes.iter().all(|hash| !small.hashes.contains(hash)));
+        // Each 8-byte chunk is two whole 4-byte chunks; 4-byte chunks split
+        // 8-byte ones and have to be re-read
+        assert_eq!((large.novel, large.derivable), (vec![], vec![0, 1]));
+        assert_eq!((small.novel, small.derivable), (vec![0, 1, 2, 3], vec![]));
+
+        // Sizes that don't divide: of the 5-byte chunks over 14 bytes only
+        // [10, 14) lines up, covering the 2-byte chunks [10, 12) and [12, 14)
+        let uneven = rechunk_hashes(b"abcdefghijklmn", 2, 5).unwrap();
+        assert_eq!((uneven.novel, uneven.derivable), (vec![0, 1], vec![2]));
+
+        // A tail shorter than both sizes is the same chunk either way
+        let rechunked = rechunk_hashes(b"abc", 4, 8).unwrap();
+        assert!(rechunked.novel.is_empty() && rechunked.derivable.is_empty());
+        assert!(rechunk_hashes(data, 0, 8).is_err());
+    }
+
+    #[test]
+    fn streaming_addresser_matches_content_address() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut addresser = StreamingAddresser::new();
+        for chunk in data.chunks(4096) {
+            addresser.update(chunk);
+        }
+        assert_eq!(addresser.finish(), content_address(&data));
+
+        // finish starts over for the next stream
+        addresser.update(&data[..3]);
+        addresser.update(&data[3..10]);
+        assert_eq!(addresser.finish(), content_address(&data[..10]));
+        assert_eq!(addresser.finish(), content_address(&[]));
+    }
 }